@@ -0,0 +1,255 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use std::{collections::HashSet, io::Write};
+
+use crate::addressbook::{counterparty, display};
+use crate::category::{categorize, Category};
+use crate::sink::TransferSink;
+use crate::types::*;
+
+// SOL has 9 decimals; fees are paid in lamports
+pub const SOL_DECIMALS: u8 = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum AccountingPreset {
+    /// Koinly universal import format
+    Koinly,
+    /// CoinTracker CSV import
+    Cointracker,
+    /// CoinLedger universal import format
+    Coinledger,
+    /// Generic double-entry rows: date, account, debit, credit, memo, tx_hash
+    DoubleEntry,
+}
+
+impl AccountingPreset {
+    fn header(&self) -> &'static [&'static str] {
+        match self {
+            AccountingPreset::Koinly => &[
+                "Date",
+                "Sent Amount",
+                "Sent Currency",
+                "Received Amount",
+                "Received Currency",
+                "Fee Amount",
+                "Fee Currency",
+                "Net Worth Amount",
+                "Net Worth Currency",
+                "Label",
+                "Description",
+                "TxHash",
+            ],
+            AccountingPreset::Cointracker => &[
+                "Date",
+                "Received Quantity",
+                "Received Currency",
+                "Sent Quantity",
+                "Sent Currency",
+                "Fee Amount",
+                "Fee Currency",
+                "Tag",
+            ],
+            AccountingPreset::Coinledger => &[
+                "Date (UTC)",
+                "Platform (Optional)",
+                "Asset Sent",
+                "Amount Sent",
+                "Asset Received",
+                "Amount Received",
+                "Fee Currency (Optional)",
+                "Fee Amount (Optional)",
+                "Type",
+                "Description (Optional)",
+                "TXID (Optional)",
+            ],
+            AccountingPreset::DoubleEntry => &["date", "account", "debit", "credit", "memo", "tx_hash"],
+        }
+    }
+}
+
+// Writes transfers as CSV in the layout of an accounting or tax tool. Of a
+// failed attempt only the fee shows up, and only in double-entry output. The
+// fee of a transaction is only booked once per wallet, and only if the wallet
+// paid it.
+pub struct AccountingSink {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+    preset: AccountingPreset,
+    header_written: bool,
+    // (wallet, signature) of fees already booked
+    fees_booked: HashSet<(String, String)>,
+}
+
+impl AccountingSink {
+    pub fn new(writer: Box<dyn Write + Send>, preset: AccountingPreset) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            preset,
+            header_written: false,
+            fees_booked: HashSet::new(),
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if !self.header_written {
+            self.writer.write_record(self.preset.header())?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    // Fee in SOL the wallet paid for the transaction, if not booked yet
+    fn take_fee(&mut self, wallet: &str, transfer: &UsdcTransfer) -> Option<String> {
+        let fee = transfer.fee_lamports.filter(|_| transfer.fee_payer.as_deref() == Some(wallet))?;
+        self.fees_booked
+            .insert((wallet.to_string(), transfer.signature.clone()))
+            .then(|| format_units(fee, SOL_DECIMALS))
+    }
+
+    fn records(&mut self, wallet: &str, transfer: &UsdcTransfer) -> Vec<Vec<String>> {
+        let failed = !transfer.moved_funds();
+        if failed && self.preset != AccountingPreset::DoubleEntry {
+            return Vec::new();
+        }
+
+        let fee = self.take_fee(wallet, transfer);
+        let amount = format_units(transfer.raw_amount, transfer.decimals);
+        let memo = memo(transfer);
+        let (sent, received) = if transfer.is_incoming {
+            (String::new(), amount.clone())
+        } else {
+            (amount.clone(), String::new())
+        };
+        let currency = |value: &str, currency: &str| if value.is_empty() { "" } else { currency }.to_string();
+        let fee_amount = fee.clone().unwrap_or_default();
+        let fee_currency = currency(&fee_amount, "SOL");
+
+        match self.preset {
+            AccountingPreset::Koinly => vec![vec![
+                transfer.timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                sent.clone(),
+                currency(&sent, "USDC"),
+                received.clone(),
+                currency(&received, "USDC"),
+                fee_amount,
+                fee_currency,
+                String::new(),
+                String::new(),
+                String::new(),
+                memo,
+                transfer.signature.clone(),
+            ]],
+            AccountingPreset::Cointracker => vec![vec![
+                transfer.timestamp.format("%m/%d/%Y %H:%M:%S").to_string(),
+                received.clone(),
+                currency(&received, "USDC"),
+                sent.clone(),
+                currency(&sent, "USDC"),
+                fee_amount,
+                fee_currency,
+                String::new(),
+            ]],
+            AccountingPreset::Coinledger => vec![vec![
+                transfer.timestamp.format("%m/%d/%Y %H:%M:%S").to_string(),
+                "Solana".to_string(),
+                currency(&sent, "USDC"),
+                sent,
+                currency(&received, "USDC"),
+                received,
+                fee_currency,
+                fee_amount,
+                if transfer.is_incoming { "Deposit" } else { "Withdrawal" }.to_string(),
+                memo,
+                transfer.signature.clone(),
+            ]],
+            AccountingPreset::DoubleEntry => {
+                let date = transfer.timestamp.format("%Y-%m-%d").to_string();
+                let entry = |account: String, debit: &str, credit: &str, memo: &str| {
+                    vec![
+                        date.clone(),
+                        account,
+                        debit.to_string(),
+                        credit.to_string(),
+                        memo.to_string(),
+                        transfer.signature.clone(),
+                    ]
+                };
+
+                let mut entries = Vec::new();
+                // Internal transfers are booked once, from the sending side
+                if !failed && !(transfer.is_internal && transfer.is_incoming) {
+                    let (debit, credit) = if transfer.is_incoming {
+                        (wallet_account(wallet, "USDC"), counterparty_account(transfer))
+                    } else {
+                        (counterparty_account(transfer), wallet_account(wallet, "USDC"))
+                    };
+                    entries.push(entry(debit, &amount, "", &memo));
+                    entries.push(entry(credit, "", &amount, &memo));
+                }
+                if let Some(fee) = fee {
+                    entries.push(entry("Expenses:Fees:SOL".to_string(), &fee, "", "Solana transaction fee"));
+                    entries.push(entry(wallet_account(wallet, "SOL"), "", &fee, "Solana transaction fee"));
+                }
+                entries
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransferSink for AccountingSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.write_header()?;
+        for transfer in transfers {
+            for record in self.records(wallet, transfer) {
+                self.writer.write_record(record)?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Exact decimal string of an amount in base units, e.g. 1500000 with 6
+// decimals is "1.500000"
+pub fn format_units(raw: u64, decimals: u8) -> String {
+    if decimals == 0 {
+        return raw.to_string();
+    }
+    let scale = 10u64.pow(decimals as u32);
+    format!("{}.{:0width$}", raw / scale, raw % scale, width = decimals as usize)
+}
+
+fn wallet_account(wallet: &str, currency: &str) -> String {
+    format!("Assets:Solana:{}:{}", wallet, currency)
+}
+
+// The other side of a transfer: another group wallet for internal ones,
+// otherwise income or expenses by category and counterparty
+fn counterparty_account(transfer: &UsdcTransfer) -> String {
+    let counterparty = if transfer.is_incoming { &transfer.from_address } else { &transfer.to_address };
+    if transfer.is_internal {
+        return wallet_account(counterparty, "USDC");
+    }
+
+    let category = match categorize(transfer)[0] {
+        Category::Swap => "Swaps",
+        Category::Bridge => "Bridges",
+        _ => "Payments",
+    };
+    let root = if transfer.is_incoming { "Income" } else { "Expenses" };
+    format!("{}:{}:{}", root, category, counterparty)
+}
+
+pub fn memo(transfer: &UsdcTransfer) -> String {
+    let categories: Vec<&str> = categorize(transfer).iter().map(|c| c.as_str()).collect();
+    let direction = if transfer.is_incoming { "from" } else { "to" };
+    let (address, label) = counterparty(transfer);
+    format!("USDC {} {} {}", categories.join(", "), direction, display(address, label))
+}
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::types::*;
+
+// Display names for programs commonly seen around USDC movements. These also
+// label `program_path`, which categories match on, so they are not taken from
+// the editable address book.
+const PROGRAMS: &[(&str, &str)] = &[
+    (SPL_TOKEN_PROGRAM_ID, "Token"),
+    ("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb", "Token-2022"),
+    (ASSOCIATED_TOKEN_PROGRAM_ID, "Associated Token"),
+    (SYSTEM_PROGRAM_ID, "System"),
+    ("ComputeBudget111111111111111111111111111111", "Compute Budget"),
+    ("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", "Memo"),
+    ("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo", "Memo v1"),
+    ("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4", "Jupiter"),
+    ("JUP4Fb2cqiRUcaTHdrPC8h2gNsA2ETXiPDD33WcGuJB", "Jupiter v4"),
+    ("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc", "Whirlpool"),
+    ("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8", "Raydium AMM"),
+    ("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK", "Raydium CLMM"),
+    ("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C", "Raydium CPMM"),
+    ("LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo", "Meteora DLMM"),
+    ("Eo7WjKq67rjJQSZxS6z3YkapzY3eMj6Xy8X5EQVn5UaB", "Meteora Pools"),
+    ("PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY", "Phoenix"),
+    ("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX", "OpenBook"),
+    ("opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb", "OpenBook v2"),
+    ("wormDTUJ6AWPNvk59vGQbDvGJmqbDTdgWgAqcLBCgUb", "Wormhole Token Bridge"),
+    ("CCTPiPYPc6AsJuwueEnWgSgucamXDZwBd53dQ11YiKX3", "CCTP Token Messenger"),
+    ("CCTPmbSD7gX1bxKPAmg77w8oFzNFpaQiQUWD43TKaecd", "CCTP Message Transmitter"),
+];
+
+// Publicly labelled exchange hot wallets
+const EXCHANGES: &[(&str, &str)] = &[
+    ("9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM", "Binance"),
+    ("5tzFkiKscXHK5ZXCGbXZxdw7gTjjD1mBwuoFbhUvuAi9", "Binance 2"),
+    ("H8sMJSCQxfKiFTCfDR3DUMLPwcRbM61LGFJ8N4dK3WjS", "Coinbase"),
+    ("2AQdpHJ2JpcEgPiATUXjQxA8QmafFegfQwSLWSprPicm", "Coinbase 2"),
+    ("FWznbcNXWQuHTawe9RxvQ2LdCENssh12dsznf4RiouN5", "Kraken"),
+    ("5VCwKtCXgCJ6kit5FybXjvriW3xELsFDhYrPSqtJNmcD", "OKX"),
+    ("AC5RDfQFmDS1deWZos921JfqscXdByf8BKHs5ACWjtW2", "Bybit"),
+];
+
+pub fn program_name(program_id: &str) -> Option<&'static str> {
+    PROGRAMS.iter().find(|(id, _)| *id == program_id).map(|(_, name)| *name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityType {
+    /// A wallet of our own or a known party
+    Wallet,
+    /// Centralized exchange
+    Exchange,
+    /// On-chain program
+    Program,
+    /// DeFi protocol, bridge or other service
+    Protocol,
+    /// Business, vendor or customer
+    Business,
+    /// Individual
+    Person,
+    /// Anything else
+    Other,
+}
+
+impl EntityType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntityType::Wallet => "wallet",
+            EntityType::Exchange => "exchange",
+            EntityType::Program => "program",
+            EntityType::Protocol => "protocol",
+            EntityType::Business => "business",
+            EntityType::Person => "person",
+            EntityType::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub address: String,
+    pub label: String,
+    #[serde(default = "other")]
+    pub entity_type: EntityType,
+}
+
+fn other() -> EntityType {
+    EntityType::Other
+}
+
+// Labels and entity types for addresses, from the well-known entries shipped
+// with the crate and an optional file of our own, which wins over them. The
+// file is JSON (an array of entries) or CSV with an `address,label,entity_type`
+// header, by extension.
+#[derive(Debug, Clone)]
+pub struct AddressBook {
+    path: Option<PathBuf>,
+    builtin: BTreeMap<String, AddressEntry>,
+    entries: BTreeMap<String, AddressEntry>,
+}
+
+impl Default for AddressBook {
+    fn default() -> Self {
+        let programs = PROGRAMS.iter().map(|(address, label)| (address, label, EntityType::Program));
+        let exchanges = EXCHANGES.iter().map(|(address, label)| (address, label, EntityType::Exchange));
+        let builtin = programs
+            .chain(exchanges)
+            .map(|(address, label, entity_type)| {
+                let entry = AddressEntry {
+                    address: address.to_string(),
+                    label: label.to_string(),
+                    entity_type,
+                };
+                (entry.address.clone(), entry)
+            })
+            .collect();
+
+        Self {
+            path: None,
+            builtin,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl AddressBook {
+    // Open the book kept in `path`; a missing file is an empty book that is
+    // created on the first save
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = if path.exists() { read_entries(&path)? } else { Vec::new() };
+        let mut book = Self {
+            path: Some(path),
+            ..Self::default()
+        };
+        book.insert_all(entries);
+        Ok(book)
+    }
+
+    pub fn get(&self, address: &str) -> Option<&AddressEntry> {
+        self.entries.get(address).or_else(|| self.builtin.get(address))
+    }
+
+    pub fn label(&self, address: &str) -> Option<&str> {
+        self.get(address).map(|entry| entry.label.as_str())
+    }
+
+    // Our own entries, or those merged over the well-known ones
+    pub fn entries(&self, include_builtin: bool) -> Vec<&AddressEntry> {
+        if !include_builtin {
+            return self.entries.values().collect();
+        }
+        let mut all: BTreeMap<&str, &AddressEntry> = self.builtin.iter().map(|(a, e)| (a.as_str(), e)).collect();
+        all.extend(self.entries.iter().map(|(a, e)| (a.as_str(), e)));
+        all.into_values().collect()
+    }
+
+    pub fn insert(&mut self, entry: AddressEntry) {
+        self.entries.insert(entry.address.clone(), entry);
+    }
+
+    pub fn remove(&mut self, address: &str) -> bool {
+        self.entries.remove(address).is_some()
+    }
+
+    // Add or replace entries from a JSON or CSV file; returns how many
+    pub fn import(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        let entries = read_entries(path.as_ref())?;
+        let count = entries.len();
+        self.insert_all(entries);
+        Ok(count)
+    }
+
+    pub fn export(&self, path: impl AsRef<Path>, include_builtin: bool) -> Result<usize> {
+        let entries = self.entries(include_builtin);
+        write_entries(path.as_ref(), &entries)?;
+        Ok(entries.len())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = self.path.as_ref().ok_or_else(|| anyhow!("No address book file; pass --address-book"))?;
+        write_entries(path, &self.entries(false))
+    }
+
+    // Fill in the labels of both sides of each transfer
+    pub fn label_transfers(&self, transfers: &mut [UsdcTransfer]) {
+        for transfer in transfers {
+            transfer.from_label = self.label(&transfer.from_address).map(str::to_string);
+            transfer.to_label = self.label(&transfer.to_address).map(str::to_string);
+        }
+    }
+
+    fn insert_all(&mut self, entries: Vec<AddressEntry>) {
+        for entry in entries {
+            self.insert(entry);
+        }
+    }
+}
+
+// The other side of a transfer and its label, if known
+pub fn counterparty(transfer: &UsdcTransfer) -> (&str, Option<&str>) {
+    if transfer.is_incoming {
+        (&transfer.from_address, transfer.from_label.as_deref())
+    } else {
+        (&transfer.to_address, transfer.to_label.as_deref())
+    }
+}
+
+// "Label (address)" for labelled addresses, otherwise the address
+pub fn display(address: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{} ({})", label, address),
+        None => address.to_string(),
+    }
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+fn read_entries(path: &Path) -> Result<Vec<AddressEntry>> {
+    if is_csv(path) {
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+        return reader
+            .deserialize()
+            .map(|row| row.map_err(|e| anyhow!("Invalid address book entry in {}: {}", path.display(), e)))
+            .collect();
+    }
+
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| anyhow!("Invalid address book {}: {}", path.display(), e))
+}
+
+fn write_entries(path: &Path, entries: &[&AddressEntry]) -> Result<()> {
+    let file = fs::File::create(path).map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+
+    if is_csv(path) {
+        let mut writer = csv::Writer::from_writer(file);
+        if entries.is_empty() {
+            writer.write_record(["address", "label", "entity_type"])?;
+        }
+        for entry in entries {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let mut writer = std::io::BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, entries)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
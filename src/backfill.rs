@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::time::Duration as StdDuration;
+use tracing::{info, warn};
+
+use crate::indexer::SolanaIndexer;
+use crate::sink::TransferSink;
+use crate::sqlite::SqliteStore;
+use crate::types::*;
+
+// Attempts per chunk within one run before it is reported as failed
+const MAX_CHUNK_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+// A long backfill of one wallet, split into time chunks whose progress is
+// recorded so an interrupted run picks up where it stopped
+#[derive(Debug, Clone)]
+pub struct BackfillJob {
+    pub id: i64,
+    pub wallet: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    // Whether all signatures of the range were recorded in the chunks, and
+    // if not, the `before` cursor to continue planning from
+    pub planned: bool,
+    pub plan_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackfillChunk {
+    pub index: usize,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    // Signatures falling into the chunk, found when the job was planned
+    pub signatures: Vec<GetSignaturesForAddressResponse>,
+    pub status: ChunkStatus,
+    pub attempts: u32,
+    pub transfers_found: Option<usize>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct BackfillSummary {
+    pub job: BackfillJob,
+    pub chunks: usize,
+    pub done: usize,
+    pub transfers_found: usize,
+    pub failed: Vec<BackfillChunk>,
+}
+
+pub struct Backfill<'a> {
+    indexer: &'a SolanaIndexer,
+    jobs: &'a mut SqliteStore,
+    hours_back: u64,
+    chunk_hours: u64,
+}
+
+impl<'a> Backfill<'a> {
+    pub fn new(indexer: &'a SolanaIndexer, jobs: &'a mut SqliteStore, hours_back: u64, chunk_hours: u64) -> Self {
+        Self {
+            indexer,
+            jobs,
+            hours_back,
+            chunk_hours: chunk_hours.max(1),
+        }
+    }
+
+    // Run the wallet's unfinished job with the same range and chunk size, or
+    // plan a new one, indexing every chunk that is not done yet
+    pub async fn run(&mut self, wallet: Pubkey, sink: &mut dyn TransferSink) -> Result<BackfillSummary> {
+        let wallet_address = wallet.to_string();
+
+        let job = match self.jobs.find_open_job(&wallet_address, self.hours_back, self.chunk_hours)? {
+            Some(job) => {
+                info!(job = job.id, wallet = %wallet_address, "Resuming backfill job");
+                job
+            }
+            None => self.create_job(wallet)?,
+        };
+        if !job.planned {
+            self.plan(&job).await?;
+        }
+
+        let mut chunks = self.jobs.load_chunks(job.id)?;
+        let total = chunks.len();
+
+        for chunk in chunks.iter_mut().filter(|c| c.status != ChunkStatus::Done) {
+            info!(
+                chunk = chunk.index + 1,
+                chunks = total,
+                start = %chunk.start,
+                end = %chunk.end,
+                signatures = chunk.signatures.len(),
+                "Indexing backfill chunk"
+            );
+            self.run_chunk(&job, chunk, sink).await?;
+        }
+
+        let failed: Vec<_> = chunks
+            .iter()
+            .filter(|c| c.status == ChunkStatus::Failed)
+            .cloned()
+            .collect();
+        if failed.is_empty() {
+            self.jobs.complete_job(job.id)?;
+        }
+
+        Ok(BackfillSummary {
+            chunks: total,
+            done: chunks.iter().filter(|c| c.status == ChunkStatus::Done).count(),
+            transfers_found: chunks.iter().filter_map(|c| c.transfers_found).sum(),
+            failed,
+            job,
+        })
+    }
+
+    // Save a job for the range with one empty chunk per `chunk_hours`
+    fn create_job(&mut self, wallet: Pubkey) -> Result<BackfillJob> {
+        let end = Utc::now();
+        let start = end - Duration::hours(self.hours_back as i64);
+        let chunk_length = Duration::hours(self.chunk_hours as i64);
+        let chunk_count = self.hours_back.div_ceil(self.chunk_hours).max(1) as usize;
+
+        let chunks: Vec<BackfillChunk> = (0..chunk_count)
+            .map(|index| {
+                let chunk_start = start + chunk_length * index as i32;
+                BackfillChunk {
+                    index,
+                    start: chunk_start,
+                    end: (chunk_start + chunk_length).min(end),
+                    signatures: Vec::new(),
+                    status: ChunkStatus::Pending,
+                    attempts: 0,
+                    transfers_found: None,
+                    last_error: None,
+                }
+            })
+            .collect();
+
+        let job = self.jobs.create_job(&wallet.to_string(), self.hours_back, self.chunk_hours, start, end, &chunks)?;
+        info!(job = job.id, hours = self.hours_back, chunks = chunk_count, "Created backfill job");
+        Ok(job)
+    }
+
+    // Find the signatures of the range and record them per chunk, saving
+    // each page with the cursor of the next so an interrupted plan resumes
+    async fn plan(&mut self, job: &BackfillJob) -> Result<()> {
+        let wallet: Pubkey = job.wallet.parse()?;
+        let chunks = self.jobs.load_chunks(job.id)?;
+        let chunk_count = chunks.len();
+        let mut before = job.plan_cursor.clone();
+
+        info!(job = job.id, resumed = before.is_some(), "Planning backfill");
+
+        // Signatures come newest first; one without a block time joins the
+        // chunk of the signature before it, which after a restart is the
+        // oldest chunk planned so far
+        let mut index = chunks
+            .iter()
+            .find(|c| !c.signatures.is_empty())
+            .map_or(chunk_count - 1, |c| c.index);
+        let mut found: usize = chunks.iter().map(|c| c.signatures.len()).sum();
+
+        loop {
+            let (page, next) = self
+                .indexer
+                .get_signature_page_between(&wallet, job.start, job.end, before.as_deref())
+                .await?;
+
+            let mut by_chunk = vec![Vec::new(); chunk_count];
+            found += page.len();
+            for sig in page {
+                if let Some(block_time) = sig.block_time {
+                    let offset = (block_time - job.start.timestamp()).max(0) as u64;
+                    index = ((offset / (self.chunk_hours * 3600)) as usize).min(chunk_count - 1);
+                }
+                by_chunk[index].push(sig);
+            }
+            self.jobs.add_planned_signatures(job.id, &by_chunk, next.as_deref())?;
+            info!(signatures = found, "Found signatures so far");
+
+            match next {
+                Some(next) => before = Some(next),
+                None => return Ok(()),
+            }
+
+            // Small delay between pages to be respectful to RPC
+            tokio::time::sleep(StdDuration::from_millis(100)).await;
+        }
+    }
+
+    // Index a chunk, retrying with backoff; after MAX_CHUNK_ATTEMPTS the
+    // chunk is marked failed and left for the next run
+    async fn run_chunk(&mut self, job: &BackfillJob, chunk: &mut BackfillChunk, sink: &mut dyn TransferSink) -> Result<()> {
+        for attempt in 1..=MAX_CHUNK_ATTEMPTS {
+            chunk.attempts += 1;
+
+            match self.index_chunk(job, chunk, sink).await {
+                Ok(transfers_found) => {
+                    chunk.status = ChunkStatus::Done;
+                    chunk.transfers_found = Some(transfers_found);
+                    chunk.last_error = None;
+                    return self.jobs.update_chunk(job.id, chunk);
+                }
+                Err(e) => {
+                    warn!(chunk = chunk.index + 1, attempt, error = %e, "Backfill chunk failed");
+                    chunk.last_error = Some(e.to_string());
+                    if attempt == MAX_CHUNK_ATTEMPTS {
+                        chunk.status = ChunkStatus::Failed;
+                    }
+                    self.jobs.update_chunk(job.id, chunk)?;
+                }
+            }
+
+            if attempt < MAX_CHUNK_ATTEMPTS {
+                tokio::time::sleep(StdDuration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn index_chunk(&self, job: &BackfillJob, chunk: &BackfillChunk, sink: &mut dyn TransferSink) -> Result<usize> {
+        let wallet: Pubkey = job.wallet.parse()?;
+
+        sink.begin(&job.wallet, self.hours_back).await?;
+        // Balances after each transaction stay unknown: the chunk ends in the
+        // past and the wallet's accounts at that point are not known
+        let result = self.indexer.process_signatures(wallet, &chunk.signatures, None, sink).await;
+
+        // Finish the sink run of a failed chunk too, with what was written
+        let transfers_found = result.as_ref().map_or(0, |activity| activity.transfers.len());
+        sink.finish(&job.wallet, transfers_found).await?;
+
+        let activity = result?;
+        if activity.skipped > 0 {
+            return Err(anyhow!("{} transactions could not be fetched or parsed", activity.skipped));
+        }
+
+        Ok(activity.transfers.len())
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::types::*;
+
+// A wallet's USDC token accounts touched by a transaction, with their balances
+// before and after it (None where the account did not exist)
+pub type TouchedAccounts = BTreeMap<String, (Option<u64>, Option<u64>)>;
+
+// Balances of all of a wallet's USDC token accounts, taken at a slot and
+// walked back through its transactions newest first. The total after a
+// transaction takes the accounts it touched from the transaction itself and
+// carries the others over from the newer state.
+pub struct AccountBalances {
+    // Slot of the snapshot; newer transactions are not part of it
+    slot: u64,
+    // None once a transaction could not be applied
+    accounts: Option<HashMap<String, u64>>,
+}
+
+impl AccountBalances {
+    pub fn new(slot: u64, accounts: HashMap<String, u64>) -> Self {
+        Self {
+            slot,
+            accounts: Some(accounts),
+        }
+    }
+
+    // Total after the transaction, stepping back to the balances before it
+    pub fn step_back(&mut self, slot: u64, touched: &TouchedAccounts) -> Option<u64> {
+        if slot > self.slot {
+            return None;
+        }
+        let accounts = self.accounts.as_mut()?;
+
+        for (address, (_, post)) in touched {
+            set_balance(accounts, address, *post);
+        }
+        let total = accounts.values().sum();
+        for (address, (pre, _)) in touched {
+            set_balance(accounts, address, *pre);
+        }
+        Some(total)
+    }
+
+    // A transaction could not be fetched, so totals before it are unknown
+    pub fn lose_track(&mut self) {
+        self.accounts = None;
+    }
+}
+
+fn set_balance(accounts: &mut HashMap<String, u64>, address: &str, balance: Option<u64>) {
+    match balance {
+        Some(balance) => accounts.insert(address.to_string(), balance),
+        None => accounts.remove(address),
+    };
+}
+
+// Oldest first by chain position, keeping instruction order within a
+// transaction
+pub fn chain_order(a: &UsdcTransfer, b: &UsdcTransfer) -> Ordering {
+    (a.slot, a.tx_index, &a.signature, a.instruction_index, a.inner_index)
+        .cmp(&(b.slot, b.tx_index, &b.signature, b.instruction_index, b.inner_index))
+}
+
+// Change of the wallet's USDC in base units
+pub fn signed_amount(transfer: &UsdcTransfer) -> i128 {
+    match (transfer.moved_funds(), transfer.is_incoming) {
+        (false, _) => 0,
+        (true, true) => transfer.raw_amount as i128,
+        (true, false) => -(transfer.raw_amount as i128),
+    }
+}
+
+// USDC balance of a wallet before the first of its transfers, given in chain
+// order: the first known balance after a transaction minus everything that
+// moved up to and including that transaction
+pub fn opening_balance<'a>(transfers: impl IntoIterator<Item = &'a UsdcTransfer>) -> Option<i128> {
+    let transfers: Vec<&UsdcTransfer> = transfers.into_iter().collect();
+    let mut moved = 0;
+
+    for (i, transfer) in transfers.iter().enumerate() {
+        moved += signed_amount(transfer);
+
+        let last_of_transaction = transfers.get(i + 1).is_none_or(|next| next.signature != transfer.signature);
+        if !last_of_transaction {
+            continue;
+        }
+        let balance = transfers[..=i]
+            .iter()
+            .rev()
+            .take_while(|t| t.signature == transfer.signature)
+            .find_map(|t| t.balance_after);
+        if let Some(balance) = balance {
+            return Some(balance as i128 - moved);
+        }
+    }
+    None
+}
+
+// Fill in `balance_after` where it is missing (transactions without post
+// token balances, rows stored before balances were recorded) by running the
+// opening balance forward, and set the activity's opening and closing balance
+pub fn fill_balances(activity: &mut WalletActivity) {
+    let mut order: Vec<usize> = (0..activity.transfers.len()).collect();
+    order.sort_by(|a, b| chain_order(&activity.transfers[*a], &activity.transfers[*b]));
+
+    let Some(opening) = opening_balance(order.iter().map(|i| &activity.transfers[*i])) else {
+        return;
+    };
+
+    let transactions: Vec<Vec<usize>> = order
+        .chunk_by(|a, b| activity.transfers[*a].signature == activity.transfers[*b].signature)
+        .map(<[usize]>::to_vec)
+        .collect();
+
+    let mut balance = opening;
+    for group in transactions {
+        let transfers = &mut activity.transfers;
+        balance += group.iter().map(|i| signed_amount(&transfers[*i])).sum::<i128>();
+        // Recorded balances win over reconstructed ones
+        if let Some(recorded) = group.iter().find_map(|i| transfers[*i].balance_after) {
+            balance = recorded as i128;
+        }
+        for i in group {
+            if transfers[i].balance_after.is_none() && balance >= 0 {
+                transfers[i].balance_after = Some(balance as u64);
+            }
+        }
+    }
+
+    activity.opening_balance = Some(ui_amount(opening));
+    activity.closing_balance = Some(ui_amount(balance));
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BalancePoint {
+    Time(DateTime<Utc>),
+    Slot(u64),
+}
+
+impl fmt::Display for BalancePoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalancePoint::Time(time) => write!(f, "{}", time.to_rfc3339()),
+            BalancePoint::Slot(slot) => write!(f, "slot {}", slot),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceAt {
+    pub wallet: String,
+    pub balance: f64,
+    pub raw_balance: u64,
+    // Last transaction at or before the point, if the balance comes from one
+    pub signature: Option<String>,
+    pub slot: Option<u64>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+// Balance of a wallet at a time or slot from its indexed activity: the
+// balance after the last transaction at or before the point, or the opening
+// balance for a time inside the indexed range but before any transfer
+pub fn balance_at(
+    wallet: &str,
+    activity: &WalletActivity,
+    point: BalancePoint,
+    indexed_since: DateTime<Utc>,
+) -> Result<BalanceAt> {
+    let last = activity
+        .transfers
+        .iter()
+        .filter(|t| t.balance_after.is_some())
+        .filter(|t| match point {
+            BalancePoint::Time(time) => t.timestamp <= time,
+            BalancePoint::Slot(slot) => t.slot <= slot,
+        })
+        .max_by(|a, b| chain_order(a, b));
+
+    if let Some(transfer) = last {
+        let raw_balance = transfer.balance_after.unwrap_or(0);
+        return Ok(BalanceAt {
+            wallet: wallet.to_string(),
+            balance: ui_amount(raw_balance as i128),
+            raw_balance,
+            signature: Some(transfer.signature.clone()),
+            slot: Some(transfer.slot),
+            timestamp: Some(transfer.timestamp),
+        });
+    }
+
+    match (point, activity.opening_balance) {
+        (BalancePoint::Time(time), Some(opening)) if time >= indexed_since => Ok(BalanceAt {
+            wallet: wallet.to_string(),
+            balance: opening,
+            raw_balance: (opening * 10f64.powi(USDC_DECIMALS as i32)).round() as u64,
+            signature: None,
+            slot: None,
+            timestamp: None,
+        }),
+        _ => Err(anyhow!(
+            "No balance known for {} at {}; index a longer range with --hours",
+            wallet,
+            point
+        )),
+    }
+}
+
+pub fn ui_amount(raw: i128) -> f64 {
+    raw as f64 / 10f64.powi(USDC_DECIMALS as i32)
+}
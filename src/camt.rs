@@ -0,0 +1,198 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use std::{collections::BTreeMap, io::Write};
+use tracing::warn;
+
+use crate::accounting::{format_units, memo};
+use crate::addressbook::counterparty;
+use crate::balance::{chain_order, opening_balance, signed_amount};
+use crate::sink::TransferSink;
+use crate::types::*;
+use crate::xml::escape_xml;
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+// camt.053 only allows ISO 4217 codes, so USDC is reported as USD
+const CURRENCY: &str = "USD";
+
+// Length limits of the schema's Max34Text, Max35Text and Max140Text fields
+const MAX_ACCOUNT_ID: usize = 34;
+const MAX_REFERENCE: usize = 35;
+const MAX_REMITTANCE: usize = 140;
+
+// Writes one ISO 20022 camt.053 document once all wallets are done, with a
+// statement per wallet and UTC day that had transfers. Addresses and
+// signatures are longer than the schema allows for account ids and end to
+// end references, so those hold prefixes; the full address is the account
+// name and the full signature is in the additional information.
+pub struct CamtSink {
+    writer: Box<dyn Write + Send>,
+    wallets: BTreeMap<String, Vec<UsdcTransfer>>,
+}
+
+impl CamtSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            wallets: BTreeMap::new(),
+        }
+    }
+
+    fn write_statement(
+        &mut self,
+        wallet: &str,
+        date: NaiveDate,
+        opening: i128,
+        transfers: &[&UsdcTransfer],
+    ) -> Result<i128> {
+        let closing = opening + transfers.iter().map(|t| signed_amount(t)).sum::<i128>();
+        let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+        let w = &mut self.writer;
+
+        writeln!(w, "    <Stmt>")?;
+        // Ids are limited to 35 characters, so only a wallet prefix fits
+        writeln!(w, "      <Id>USDC-{}-{}</Id>", date.format("%Y%m%d"), &wallet[..wallet.len().min(16)])?;
+        writeln!(w, "      <CreDtTm>{}</CreDtTm>", now)?;
+        writeln!(w, "      <FrToDt>")?;
+        writeln!(w, "        <FrDtTm>{}T00:00:00Z</FrDtTm>", date)?;
+        writeln!(w, "        <ToDtTm>{}T23:59:59Z</ToDtTm>", date)?;
+        writeln!(w, "      </FrToDt>")?;
+        writeln!(w, "      <Acct>")?;
+        writeln!(
+            w,
+            "        <Id><Othr><Id>{}</Id><SchmeNm><Prtry>SOLANA</Prtry></SchmeNm></Othr></Id>",
+            escape_xml(&truncate(wallet, MAX_ACCOUNT_ID))
+        )?;
+        writeln!(w, "        <Ccy>{}</Ccy>", CURRENCY)?;
+        writeln!(w, "        <Nm>{}</Nm>", escape_xml(wallet))?;
+        writeln!(w, "      </Acct>")?;
+        write_balance(w, "OPBD", opening, date)?;
+        write_balance(w, "CLBD", closing, date)?;
+
+        let credits: Vec<_> = transfers.iter().filter(|t| t.is_incoming).collect();
+        let debits: Vec<_> = transfers.iter().filter(|t| !t.is_incoming).collect();
+        writeln!(w, "      <TxsSummry>")?;
+        writeln!(w, "        <TtlNtries><NbOfNtries>{}</NbOfNtries></TtlNtries>", transfers.len())?;
+        for (tag, entries) in [("TtlCdtNtries", &credits), ("TtlDbtNtries", &debits)] {
+            let sum: u64 = entries.iter().map(|t| t.raw_amount).sum();
+            writeln!(
+                w,
+                "        <{tag}><NbOfNtries>{}</NbOfNtries><Sum>{}</Sum></{tag}>",
+                entries.len(),
+                amount(sum as i128)
+            )?;
+        }
+        writeln!(w, "      </TxsSummry>")?;
+
+        for transfer in transfers {
+            let indicator = if transfer.is_incoming { "CRDT" } else { "DBIT" };
+            let value = amount(transfer.raw_amount as i128);
+            let (party, party_account) = if transfer.is_incoming { ("Dbtr", "DbtrAcct") } else { ("Cdtr", "CdtrAcct") };
+            let (counterparty, label) = counterparty(transfer);
+
+            writeln!(w, "      <Ntry>")?;
+            writeln!(w, "        <Amt Ccy=\"{}\">{}</Amt>", CURRENCY, value)?;
+            writeln!(w, "        <CdtDbtInd>{}</CdtDbtInd>", indicator)?;
+            writeln!(w, "        <Sts><Cd>BOOK</Cd></Sts>")?;
+            writeln!(w, "        <BookgDt><DtTm>{}</DtTm></BookgDt>", transfer.timestamp.format("%Y-%m-%dT%H:%M:%SZ"))?;
+            writeln!(w, "        <ValDt><Dt>{}</Dt></ValDt>", transfer.timestamp.date_naive())?;
+            writeln!(w, "        <BkTxCd><Prtry><Cd>USDC-TRANSFER</Cd></Prtry></BkTxCd>")?;
+            writeln!(w, "        <NtryDtls>")?;
+            writeln!(w, "          <TxDtls>")?;
+            writeln!(
+                w,
+                "            <Refs><EndToEndId>{}</EndToEndId></Refs>",
+                escape_xml(&truncate(&transfer.signature, MAX_REFERENCE))
+            )?;
+            writeln!(w, "            <Amt Ccy=\"{}\">{}</Amt>", CURRENCY, value)?;
+            writeln!(w, "            <CdtDbtInd>{}</CdtDbtInd>", indicator)?;
+            writeln!(w, "            <RltdPties>")?;
+            writeln!(w, "              <{party}><Pty><Nm>{}</Nm></Pty></{party}>", escape_xml(label.unwrap_or(counterparty)))?;
+            writeln!(
+                w,
+                "              <{party_account}><Id><Othr><Id>{}</Id></Othr></Id><Nm>{}</Nm></{party_account}>",
+                escape_xml(&truncate(counterparty, MAX_ACCOUNT_ID)),
+                escape_xml(counterparty)
+            )?;
+            writeln!(w, "            </RltdPties>")?;
+            writeln!(w, "            <RmtInf><Ustrd>{}</Ustrd></RmtInf>", escape_xml(&truncate(&memo(transfer), MAX_REMITTANCE)))?;
+            writeln!(w, "            <AddtlTxInf>{}</AddtlTxInf>", escape_xml(&transfer.signature))?;
+            writeln!(w, "          </TxDtls>")?;
+            writeln!(w, "        </NtryDtls>")?;
+            writeln!(w, "        <AddtlNtryInf>{}</AddtlNtryInf>", escape_xml(&transfer.signature))?;
+            writeln!(w, "      </Ntry>")?;
+        }
+
+        writeln!(w, "    </Stmt>")?;
+        Ok(closing)
+    }
+}
+
+#[async_trait]
+impl TransferSink for CamtSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.wallets
+            .entry(wallet.to_string())
+            .or_default()
+            .extend(transfers.iter().filter(|t| t.moved_funds()).cloned());
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let now = Utc::now();
+        writeln!(self.writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(self.writer, "<Document xmlns=\"{}\">", NAMESPACE)?;
+        writeln!(self.writer, "  <BkToCstmrStmt>")?;
+        writeln!(self.writer, "    <GrpHdr>")?;
+        writeln!(self.writer, "      <MsgId>USDC-{}</MsgId>", now.format("%Y%m%d%H%M%S"))?;
+        writeln!(self.writer, "      <CreDtTm>{}</CreDtTm>", now.format("%Y-%m-%dT%H:%M:%SZ"))?;
+        writeln!(self.writer, "    </GrpHdr>")?;
+
+        for (wallet, mut transfers) in std::mem::take(&mut self.wallets) {
+            transfers.sort_by(chain_order);
+
+            let mut balance = opening_balance(&transfers).unwrap_or_else(|| {
+                warn!(wallet = %wallet, "No balances recorded for the wallet; statements start from zero");
+                0
+            });
+
+            let mut days: BTreeMap<NaiveDate, Vec<&UsdcTransfer>> = BTreeMap::new();
+            for transfer in &transfers {
+                days.entry(transfer.timestamp.date_naive()).or_default().push(transfer);
+            }
+            for (date, day) in days {
+                balance = self.write_statement(&wallet, date, balance, &day)?;
+            }
+        }
+
+        writeln!(self.writer, "  </BkToCstmrStmt>")?;
+        writeln!(self.writer, "</Document>")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_balance(w: &mut dyn Write, code: &str, balance: i128, date: NaiveDate) -> Result<()> {
+    writeln!(w, "      <Bal>")?;
+    writeln!(w, "        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>", code)?;
+    writeln!(w, "        <Amt Ccy=\"{}\">{}</Amt>", CURRENCY, amount(balance.abs()))?;
+    writeln!(w, "        <CdtDbtInd>{}</CdtDbtInd>", if balance < 0 { "DBIT" } else { "CRDT" })?;
+    writeln!(w, "        <Dt><Dt>{}</Dt></Dt>", date)?;
+    writeln!(w, "      </Bal>")?;
+    Ok(())
+}
+
+// Text cut to a schema length limit, which counts characters
+fn truncate(text: &str, max: usize) -> String {
+    text.chars().take(max).collect()
+}
+
+// Unsigned amount with at least 2 decimals; sub-cent digits are kept so
+// entries always add up to the balances
+fn amount(raw: i128) -> String {
+    let exact = format_units(raw.unsigned_abs() as u64, USDC_DECIMALS);
+    let trimmed = exact.trim_end_matches('0');
+    let decimals = trimmed.len() - trimmed.find('.').unwrap_or(trimmed.len()) - 1;
+    format!("{}{}", trimmed, "0".repeat(2usize.saturating_sub(decimals)))
+}
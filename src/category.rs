@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::*;
+
+// Program names (as labelled in `program_path`) of DEXes and aggregators
+const SWAP_PROGRAMS: &[&str] = &[
+    "Jupiter",
+    "Jupiter v4",
+    "Whirlpool",
+    "Raydium AMM",
+    "Raydium CLMM",
+    "Raydium CPMM",
+    "Meteora DLMM",
+    "Meteora Pools",
+    "Phoenix",
+    "OpenBook",
+    "OpenBook v2",
+];
+
+const BRIDGE_PROGRAMS: &[&str] = &["Wormhole Token Bridge", "CCTP Token Messenger", "CCTP Message Transmitter"];
+
+// What a transfer was, derived from its flags and the programs it went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    // Plain transfer to or from another party
+    Payment,
+    // Between wallets of the same group
+    Internal,
+    Swap,
+    Bridge,
+    // Signed by a delegate rather than the owner
+    Delegated,
+    // Attempt from a failed transaction
+    Failed,
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Payment => "payment",
+            Category::Internal => "internal",
+            Category::Swap => "swap",
+            Category::Bridge => "bridge",
+            Category::Delegated => "delegated",
+            Category::Failed => "failed",
+        }
+    }
+}
+
+// Categories of a transfer: exactly one of payment, internal, swap or bridge,
+// plus delegated and failed where they apply
+pub fn categorize(transfer: &UsdcTransfer) -> Vec<Category> {
+    let via = |programs: &[&str]| transfer.program_path.iter().any(|p| programs.contains(&p.as_str()));
+
+    let mut categories = vec![if transfer.is_internal {
+        Category::Internal
+    } else if via(SWAP_PROGRAMS) {
+        Category::Swap
+    } else if via(BRIDGE_PROGRAMS) {
+        Category::Bridge
+    } else {
+        Category::Payment
+    }];
+
+    if transfer.authority_kind == Some(AuthorityKind::Delegate) {
+        categories.push(Category::Delegated);
+    }
+    if transfer.error.is_some() {
+        categories.push(Category::Failed);
+    }
+    categories
+}
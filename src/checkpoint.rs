@@ -0,0 +1,19 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+// Newest signature processed for a wallet; the next incremental run only
+// fetches signatures after it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub signature: String,
+    pub slot: u64,
+}
+
+// Durable per-wallet checkpoints
+#[async_trait]
+pub trait CheckpointStore: Send {
+    async fn load_checkpoint(&mut self, wallet: &str) -> Result<Option<Checkpoint>>;
+
+    async fn save_checkpoint(&mut self, wallet: &str, checkpoint: &Checkpoint) -> Result<()>;
+}
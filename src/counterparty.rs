@@ -0,0 +1,152 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use crate::addressbook::{AddressBook, EntityType};
+use crate::balance::ui_amount;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Rank {
+    /// USDC received from the counterparty
+    Inflow,
+    /// USDC sent to the counterparty
+    Outflow,
+    /// USDC moved in either direction
+    Volume,
+    /// Number of transfers
+    Count,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CounterpartyStats {
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_type: Option<EntityType>,
+    // Another wallet of the same group
+    pub is_internal: bool,
+    pub total_in: f64,
+    pub total_out: f64,
+    pub net: f64,
+    pub transfers_in: usize,
+    pub transfers_out: usize,
+    pub transactions: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+// Running totals in base units while transfers are added
+struct Totals {
+    is_internal: bool,
+    total_in: u64,
+    total_out: u64,
+    transfers_in: usize,
+    transfers_out: usize,
+    signatures: HashSet<String>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+impl Totals {
+    fn rank_key(&self, rank: Rank) -> u64 {
+        match rank {
+            Rank::Inflow => self.total_in,
+            Rank::Outflow => self.total_out,
+            Rank::Volume => self.total_in + self.total_out,
+            Rank::Count => (self.transfers_in + self.transfers_out) as u64,
+        }
+    }
+}
+
+// Totals per counterparty across all given transfers, ranked by `rank` with
+// the largest first and cut to the `top` entries (0 keeps all)
+pub fn rank<'a>(
+    transfers: impl IntoIterator<Item = &'a UsdcTransfer>,
+    rank: Rank,
+    top: usize,
+    address_book: &AddressBook,
+) -> Vec<CounterpartyStats> {
+    let mut counterparties: HashMap<&str, Totals> = HashMap::new();
+
+    for transfer in transfers.into_iter().filter(|t| t.moved_funds()) {
+        let address = if transfer.is_incoming { &transfer.from_address } else { &transfer.to_address };
+        let totals = counterparties.entry(address).or_insert_with(|| Totals {
+            is_internal: transfer.is_internal,
+            total_in: 0,
+            total_out: 0,
+            transfers_in: 0,
+            transfers_out: 0,
+            signatures: HashSet::new(),
+            first_seen: transfer.timestamp,
+            last_seen: transfer.timestamp,
+        });
+
+        if transfer.is_incoming {
+            totals.total_in += transfer.raw_amount;
+            totals.transfers_in += 1;
+        } else {
+            totals.total_out += transfer.raw_amount;
+            totals.transfers_out += 1;
+        }
+        totals.signatures.insert(transfer.signature.clone());
+        totals.first_seen = totals.first_seen.min(transfer.timestamp);
+        totals.last_seen = totals.last_seen.max(transfer.timestamp);
+    }
+
+    let mut ranked: Vec<(&str, Totals)> = counterparties.into_iter().collect();
+    // Ties go to the address so the ranking is stable between runs
+    ranked.sort_by(|(a, x), (b, y)| y.rank_key(rank).cmp(&x.rank_key(rank)).then_with(|| a.cmp(b)));
+    if top > 0 {
+        ranked.truncate(top);
+    }
+
+    ranked
+        .into_iter()
+        .map(|(address, t)| CounterpartyStats {
+            address: address.to_string(),
+            label: address_book.label(address).map(str::to_string),
+            entity_type: address_book.get(address).map(|entry| entry.entity_type),
+            is_internal: t.is_internal,
+            total_in: ui_amount(t.total_in as i128),
+            total_out: ui_amount(t.total_out as i128),
+            net: ui_amount(t.total_in as i128 - t.total_out as i128),
+            transfers_in: t.transfers_in,
+            transfers_out: t.transfers_out,
+            transactions: t.signatures.len(),
+            first_seen: t.first_seen,
+            last_seen: t.last_seen,
+        })
+        .collect()
+}
+
+pub fn write_table(writer: &mut dyn Write, counterparties: &[CounterpartyStats]) -> Result<()> {
+    writeln!(writer, "🤝 Top {} counterparties", counterparties.len())?;
+    for (i, c) in counterparties.iter().enumerate() {
+        writeln!(writer)?;
+        let internal = if c.is_internal { " (group wallet)" } else { "" };
+        match (&c.label, c.entity_type) {
+            (Some(label), Some(entity_type)) => {
+                writeln!(writer, "{}. {} — {} [{}]{}", i + 1, label, c.address, entity_type.as_str(), internal)?
+            }
+            _ => writeln!(writer, "{}. {}{}", i + 1, c.address, internal)?,
+        }
+        writeln!(writer, "   In:  ${:.6} USDC in {} transfers", c.total_in, c.transfers_in)?;
+        writeln!(writer, "   Out: ${:.6} USDC in {} transfers", c.total_out, c.transfers_out)?;
+        writeln!(writer, "   Net: ${:+.6} USDC over {} transactions", c.net, c.transactions)?;
+        writeln!(
+            writer,
+            "   Seen: {} to {}",
+            c.first_seen.format("%Y-%m-%d %H:%M:%S UTC"),
+            c.last_seen.format("%Y-%m-%d %H:%M:%S UTC")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
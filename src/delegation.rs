@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use crate::types::*;
+
+// Replays delegation events in order to track allowances per token account
+#[derive(Default)]
+pub struct DelegationTracker {
+    outstanding: BTreeMap<String, Delegation>,
+}
+
+impl DelegationTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fill in the remaining allowance of each event and return the delegations
+    // still outstanding afterwards. Events are left in chronological order.
+    pub fn replay(mut self, events: &mut [DelegationEvent]) -> Vec<Delegation> {
+        events.sort_by_key(|e| (e.slot, e.tx_index, e.instruction_index));
+
+        for event in events.iter_mut() {
+            self.apply(event);
+        }
+
+        self.outstanding.into_values().collect()
+    }
+
+    fn apply(&mut self, event: &mut DelegationEvent) {
+        match event.kind {
+            DelegationKind::Approve => {
+                // A new approval replaces any previous delegate on the account
+                let delegate = event.delegate.clone().unwrap_or_default();
+                self.outstanding.insert(
+                    event.token_account.clone(),
+                    Delegation {
+                        token_account: event.token_account.clone(),
+                        delegate,
+                        allowance: event.amount,
+                        approved_at: event.timestamp,
+                    },
+                );
+                event.allowance = Some(event.amount);
+            }
+            DelegationKind::Revoke => {
+                if let Some(previous) = self.outstanding.remove(&event.token_account) {
+                    event.delegate.get_or_insert(previous.delegate);
+                }
+                event.allowance = Some(0.0);
+            }
+            DelegationKind::DelegatedSpend => {
+                let remaining = match self.outstanding.get_mut(&event.token_account) {
+                    Some(delegation) => {
+                        delegation.allowance = (delegation.allowance - event.amount).max(0.0);
+                        delegation.allowance
+                    }
+                    // Approved before the indexed range, allowance unknown
+                    None => return,
+                };
+
+                // The token program clears the delegate once the allowance is used up
+                if remaining < 0.000001 {
+                    self.outstanding.remove(&event.token_account);
+                }
+                event.allowance = Some(remaining);
+            }
+        }
+    }
+}
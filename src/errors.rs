@@ -0,0 +1,147 @@
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::InstructionError as RpcInstructionError,
+    transaction::TransactionError as RpcTransactionError,
+};
+use spl_token::error::TokenError;
+
+use crate::types::*;
+
+// Why a transaction failed, decoded from `meta.err`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransactionError {
+    InstructionError { index: u8, error: InstructionError },
+    InsufficientFundsForFee,
+    InsufficientFundsForRent { account_index: u8 },
+    AccountNotFound,
+    BlockhashNotFound,
+    AlreadyProcessed,
+    Other { name: String, message: String },
+}
+
+// Why a single instruction failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InstructionError {
+    // Program-specific error code; SPL Token codes are mapped to their names
+    Custom { code: u32, program: Option<String>, name: Option<String>, message: Option<String> },
+    InsufficientFunds,
+    MissingRequiredSignature,
+    InvalidAccountData,
+    AccountNotRentExempt,
+    Other { name: String, message: String },
+}
+
+impl TransactionError {
+    // Decode the JSON form of `meta.err`, using the logs to find the failing program
+    pub fn decode(err: &serde_json::Value, log_messages: Option<&[String]>) -> Self {
+        let rpc_error: RpcTransactionError = match serde_json::from_value(err.clone()) {
+            Ok(e) => e,
+            Err(_) => {
+                return TransactionError::Other {
+                    name: "Unknown".to_string(),
+                    message: err.to_string(),
+                }
+            }
+        };
+
+        match rpc_error {
+            RpcTransactionError::InstructionError(index, error) => TransactionError::InstructionError {
+                index,
+                error: InstructionError::decode(error, log_messages),
+            },
+            RpcTransactionError::InsufficientFundsForFee => TransactionError::InsufficientFundsForFee,
+            RpcTransactionError::InsufficientFundsForRent { account_index } => {
+                TransactionError::InsufficientFundsForRent { account_index }
+            }
+            RpcTransactionError::AccountNotFound => TransactionError::AccountNotFound,
+            RpcTransactionError::BlockhashNotFound => TransactionError::BlockhashNotFound,
+            RpcTransactionError::AlreadyProcessed => TransactionError::AlreadyProcessed,
+            other => TransactionError::Other {
+                name: variant_name(&other),
+                message: other.to_string(),
+            },
+        }
+    }
+
+    // Short human-readable reason, e.g. "instruction 2: insufficient funds"
+    pub fn describe(&self) -> String {
+        match self {
+            TransactionError::InstructionError { index, error } => {
+                format!("instruction {}: {}", index, error.describe())
+            }
+            TransactionError::InsufficientFundsForFee => "insufficient SOL for fee".to_string(),
+            TransactionError::InsufficientFundsForRent { account_index } => {
+                format!("insufficient funds for rent (account {})", account_index)
+            }
+            TransactionError::AccountNotFound => "account not found".to_string(),
+            TransactionError::BlockhashNotFound => "blockhash not found".to_string(),
+            TransactionError::AlreadyProcessed => "already processed".to_string(),
+            TransactionError::Other { message, .. } => message.clone(),
+        }
+    }
+}
+
+impl InstructionError {
+    fn decode(error: RpcInstructionError, log_messages: Option<&[String]>) -> Self {
+        match error {
+            RpcInstructionError::Custom(code) => {
+                let program = failed_program(log_messages);
+                let token_error = (program.as_deref() == Some(SPL_TOKEN_PROGRAM_ID))
+                    .then(|| TokenError::from_u32(code))
+                    .flatten();
+
+                InstructionError::Custom {
+                    code,
+                    program,
+                    name: token_error.as_ref().map(|e| format!("{:?}", e)),
+                    message: token_error.as_ref().map(|e| e.to_string()),
+                }
+            }
+            RpcInstructionError::InsufficientFunds => InstructionError::InsufficientFunds,
+            RpcInstructionError::MissingRequiredSignature => InstructionError::MissingRequiredSignature,
+            RpcInstructionError::InvalidAccountData => InstructionError::InvalidAccountData,
+            RpcInstructionError::AccountNotRentExempt => InstructionError::AccountNotRentExempt,
+            other => InstructionError::Other {
+                name: variant_name(&other),
+                message: other.to_string(),
+            },
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            InstructionError::Custom { message: Some(message), .. } => message.to_lowercase(),
+            InstructionError::Custom { code, .. } => format!("custom program error {:#x}", code),
+            InstructionError::InsufficientFunds => "insufficient funds".to_string(),
+            InstructionError::MissingRequiredSignature => "missing required signature".to_string(),
+            InstructionError::InvalidAccountData => "invalid account data".to_string(),
+            InstructionError::AccountNotRentExempt => "account not rent exempt".to_string(),
+            InstructionError::Other { message, .. } => message.clone(),
+        }
+    }
+}
+
+// Program that raised the error: the first "Program <id> failed: ..." log line,
+// before callers re-report it on the way up
+fn failed_program(log_messages: Option<&[String]>) -> Option<String> {
+    log_messages?
+        .iter()
+        .find_map(|line| {
+            let rest = line.strip_prefix("Program ")?;
+            let (program, status) = rest.split_once(' ')?;
+            status.starts_with("failed").then(|| program.to_string())
+        })
+}
+
+// Enum variant name without its fields, e.g. "DuplicateInstruction"
+fn variant_name<T: std::fmt::Debug>(value: &T) -> String {
+    let debug = format!("{:?}", value);
+    debug
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
@@ -0,0 +1,646 @@
+use async_graphql::{
+    connection::{self, Connection, Edge, OpaqueCursor},
+    http::{GraphiQLSource, WebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS},
+    Context, EmptyMutation, Enum, Error, InputObject, Object, Result, Schema, SimpleObject, Subscription,
+};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocketUpgrade},
+        State,
+    },
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderMap},
+    response::{Html, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use futures::{future, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{collections::BTreeSet, pin::pin, str::FromStr, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::addressbook::{self, AddressBook};
+use crate::balance::{balance_at, chain_order, fill_balances, ui_amount, BalanceAt, BalancePoint};
+use crate::category::categorize;
+use crate::counterparty::{self, CounterpartyStats};
+use crate::server::{self, AppState, TransferFilter, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::types::*;
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+// Nesting such as transfers -> transaction -> transfers -> transaction would
+// otherwise let one query fan out to the RPC node without bound
+const MAX_DEPTH: usize = 12;
+
+// Fields that may call the RPC node cost RPC_COST, other fields 1, and lists
+// multiply the cost of their items by the page size: a full page of transfers
+// fits within MAX_COMPLEXITY, the transaction of each of 100 transfers too,
+// but not of each of 1000
+const RPC_COST: usize = 100;
+const MAX_COMPLEXITY: usize = 25_000;
+
+pub fn schema(state: Arc<AppState>) -> ApiSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(state)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+// Cost of a page of transfers, loaded from the store or the chain
+fn page_complexity(first: Option<i32>, last: Option<i32>, child_complexity: usize) -> usize {
+    let size = first.or(last).map_or(DEFAULT_LIMIT, |n| n.max(0) as usize);
+    RPC_COST + size * child_complexity
+}
+
+// POST /graphql for queries, GET /graphql for GraphiQL and /graphql/ws for
+// subscriptions (graphql-transport-ws or the older graphql-ws protocol)
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/graphql", get(graphiql).post(graphql))
+        .route("/graphql/ws", get(graphql_ws))
+        .with_state(schema(state))
+}
+
+async fn graphql(State(schema): State<ApiSchema>, Json(request): Json<async_graphql::Request>) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
+
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").subscription_endpoint("/graphql/ws").finish())
+}
+
+async fn graphql_ws(State(schema): State<ApiSchema>, headers: HeaderMap, upgrade: WebSocketUpgrade) -> Response {
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| protocols.split(',').find_map(|p| WebSocketProtocols::from_str(p.trim()).ok()))
+        .unwrap_or(WebSocketProtocols::GraphQLWS);
+
+    upgrade.protocols(ALL_WEBSOCKET_PROTOCOLS).on_upgrade(move |socket| async move {
+        let (mut sender, receiver) = socket.split();
+        let incoming = receiver
+            .take_while(|message| future::ready(message.is_ok()))
+            .filter_map(|message| {
+                future::ready(match message {
+                    Ok(Message::Text(text)) => Some(text.as_str().as_bytes().to_vec()),
+                    Ok(Message::Binary(bytes)) => Some(bytes.to_vec()),
+                    _ => None,
+                })
+            });
+
+        let mut outgoing = pin!(WebSocket::new(schema, incoming, protocol));
+        while let Some(message) = outgoing.next().await {
+            let message = match message {
+                WsMessage::Text(text) => Message::Text(text.into()),
+                WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+            };
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn state<'a>(ctx: &Context<'a>) -> &'a Arc<AppState> {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+fn parse_wallet(address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|e| Error::new(format!("Invalid wallet address: {}", e)))
+}
+
+fn check_signature(signature: &str) -> Result<()> {
+    Signature::from_str(signature).map_err(|e| Error::new(format!("Invalid signature: {}", e)))?;
+    Ok(())
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "server::Direction")]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "addressbook::EntityType")]
+enum EntityType {
+    Wallet,
+    Exchange,
+    Program,
+    Protocol,
+    Business,
+    Person,
+    Other,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(name = "CounterpartyRank", remote = "counterparty::Rank")]
+enum Rank {
+    Inflow,
+    Outflow,
+    Volume,
+    Count,
+}
+
+// Transfers of a wallet to include; `since` defaults to the server's --hours
+#[derive(InputObject, Default)]
+#[graphql(name = "TransferFilter")]
+struct TransferFilterInput {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    direction: Option<Direction>,
+    // In USDC, e.g. 100.5
+    min_amount: Option<f64>,
+    // Only transfers with this counterparty address
+    counterparty: Option<String>,
+}
+
+// A wallet's transfers matching the filter, newest first
+async fn filtered_transfers(
+    state: &AppState,
+    wallet: &Pubkey,
+    filter: Option<TransferFilterInput>,
+) -> Result<Vec<UsdcTransfer>> {
+    let input = filter.unwrap_or_default();
+    let since = input.since.unwrap_or_else(|| state.default_since());
+    let filter = TransferFilter {
+        since: Some(since),
+        until: input.until,
+        direction: input.direction.map(Into::into),
+        min_amount: input.min_amount,
+    };
+    filter.check()?;
+
+    let (_, mut transfers) = state.load_transfers(wallet, since).await?;
+    transfers.retain(|t| {
+        filter.matches(t) && input.counterparty.as_ref().is_none_or(|c| addressbook::counterparty(t).0 == c)
+    });
+    transfers.sort_by(|a, b| chain_order(b, a));
+    Ok(transfers)
+}
+
+// Position of a transfer in a wallet's list, for cursors; the same fields
+// that keep one leg apart from another in the store
+#[derive(Serialize, Deserialize, PartialEq)]
+struct TransferKey {
+    wallet: String,
+    signature: String,
+    instruction_index: Option<usize>,
+    inner_index: Option<usize>,
+    incoming: bool,
+    token_account: Option<String>,
+}
+
+impl TransferKey {
+    fn of(wallet: &str, transfer: &UsdcTransfer) -> Self {
+        Self {
+            wallet: wallet.to_string(),
+            signature: transfer.signature.clone(),
+            instruction_index: transfer.instruction_index,
+            inner_index: transfer.inner_index,
+            incoming: transfer.is_incoming,
+            token_account: transfer.token_account.clone(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct TransferTotals {
+    // Transfers matching the filter across all pages
+    total_count: usize,
+}
+
+type TransferConnection = Connection<OpaqueCursor<TransferKey>, IndexedTransfer, TransferTotals>;
+
+// One page of transfers by Relay cursor arguments; `first` defaults to 100
+fn paginate(
+    wallet: &str,
+    transfers: Vec<UsdcTransfer>,
+    after: Option<OpaqueCursor<TransferKey>>,
+    before: Option<OpaqueCursor<TransferKey>>,
+    first: Option<usize>,
+    last: Option<usize>,
+) -> Result<TransferConnection> {
+    if first.into_iter().chain(last).any(|n| n > MAX_LIMIT) {
+        return Err(Error::new(format!("first and last can be at most {}", MAX_LIMIT)));
+    }
+    let position = |cursor: &TransferKey| {
+        transfers
+            .iter()
+            .position(|t| TransferKey::of(wallet, t) == *cursor)
+            .ok_or_else(|| Error::new("Cursor does not point into these transfers"))
+    };
+
+    let mut start = match &after {
+        Some(cursor) => position(cursor)? + 1,
+        None => 0,
+    };
+    let mut end = match &before {
+        Some(cursor) => position(cursor)?.max(start),
+        None => transfers.len(),
+    };
+    match (first, last) {
+        (None, None) => end = end.min(start + DEFAULT_LIMIT),
+        (first, last) => {
+            if let Some(first) = first {
+                end = end.min(start + first);
+            }
+            if let Some(last) = last {
+                start = start.max(end.saturating_sub(last));
+            }
+        }
+    }
+
+    let total_count = transfers.len();
+    let mut page = Connection::with_additional_fields(start > 0, end < total_count, TransferTotals { total_count });
+    page.edges.extend(transfers.into_iter().skip(start).take(end - start).map(|transfer| {
+        Edge::new(
+            OpaqueCursor(TransferKey::of(wallet, &transfer)),
+            IndexedTransfer { wallet: wallet.to_string(), transfer },
+        )
+    }));
+    Ok(page)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn wallet(&self, address: String) -> Result<Wallet> {
+        Ok(Wallet { address: parse_wallet(&address)? })
+    }
+
+    // Wallets with transfers in the store by address, `first` (default 100,
+    // at most 1000) from `offset` on
+    #[graphql(complexity = "first.map_or(DEFAULT_LIMIT, |n| n.max(0) as usize) * child_complexity")]
+    async fn wallets(&self, ctx: &Context<'_>, first: Option<i32>, offset: Option<i32>) -> Result<Vec<Wallet>> {
+        let first = first.unwrap_or(DEFAULT_LIMIT as i32);
+        if first < 1 || first as usize > MAX_LIMIT {
+            return Err(Error::new(format!("first must be between 1 and {}", MAX_LIMIT)));
+        }
+        let offset = offset.unwrap_or(0);
+        if offset < 0 {
+            return Err(Error::new("offset cannot be negative"));
+        }
+        let Some(store) = &state(ctx).store else {
+            return Err(Error::new("Listing wallets needs the server to run with --db"));
+        };
+        let addresses = store.query(|store| store.load_wallets()).await?;
+        Ok(addresses
+            .iter()
+            .filter_map(|address| Pubkey::from_str(address).ok())
+            .skip(offset as usize)
+            .take(first as usize)
+            .map(|address| Wallet { address })
+            .collect())
+    }
+
+    #[graphql(complexity = "RPC_COST + child_complexity")]
+    async fn transaction(&self, ctx: &Context<'_>, signature: String) -> Result<Option<TransactionDetails>> {
+        check_signature(&signature)?;
+        Ok(state(ctx).transaction_details(&signature).await?)
+    }
+
+    async fn counterparty(&self, address: String) -> Counterparty {
+        Counterparty { address }
+    }
+}
+
+pub struct Wallet {
+    address: Pubkey,
+}
+
+#[Object]
+impl Wallet {
+    async fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    async fn label(&self, ctx: &Context<'_>) -> Option<String> {
+        state(ctx).address_book.label(&self.address.to_string()).map(str::to_string)
+    }
+
+    #[graphql(complexity = "page_complexity(first, last, child_complexity)")]
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransferFilterInput>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<TransferConnection> {
+        let transfers = filtered_transfers(state(ctx), &self.address, filter).await?;
+        let wallet = self.address.to_string();
+        connection::query(after, before, first, last, |after, before, first, last| async move {
+            paginate(&wallet, transfers, after, before, first, last)
+        })
+        .await
+    }
+
+    // Totals per counterparty over the filtered transfers, largest first
+    #[graphql(complexity = "RPC_COST + child_complexity")]
+    async fn counterparties(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransferFilterInput>,
+        #[graphql(default = 10)] top: usize,
+        #[graphql(default_with = "Rank::Volume")] by: Rank,
+    ) -> Result<Vec<CounterpartyStats>> {
+        let state = state(ctx);
+        let transfers = filtered_transfers(state, &self.address, filter).await?;
+        Ok(counterparty::rank(&transfers, by.into(), top, &state.address_book))
+    }
+
+    // Balance at a time or slot from the indexed transfers, or the latest
+    // indexed balance without either
+    #[graphql(complexity = "RPC_COST + child_complexity")]
+    async fn balance(&self, ctx: &Context<'_>, at: Option<DateTime<Utc>>, slot: Option<u64>) -> Result<BalanceAt> {
+        let state = state(ctx);
+        let point = match (at, slot) {
+            (Some(_), Some(_)) => return Err(Error::new("Give at or slot, not both")),
+            (_, Some(slot)) => BalancePoint::Slot(slot),
+            (Some(time), None) => BalancePoint::Time(time),
+            (None, None) => BalancePoint::Time(Utc::now()),
+        };
+        let since = at.map_or(state.default_since(), |time| time.min(state.default_since()));
+
+        let (_, transfers) = state.load_transfers(&self.address, since).await?;
+        let mut activity = WalletActivity { transfers, ..Default::default() };
+        fill_balances(&mut activity);
+        Ok(balance_at(&self.address.to_string(), &activity, point, since)?)
+    }
+
+    // Current balance of all the wallet's USDC token accounts on chain
+    #[graphql(complexity = "RPC_COST")]
+    async fn onchain_balance(&self, ctx: &Context<'_>) -> Result<f64> {
+        let indexer = &state(ctx).indexer;
+        let mut raw = 0i128;
+        for account in indexer.get_usdc_accounts(&self.address).await? {
+            raw += indexer.get_token_account_balance(&account).await? as i128;
+        }
+        Ok(ui_amount(raw))
+    }
+}
+
+#[Object(name = "Transfer")]
+impl IndexedTransfer {
+    async fn wallet(&self) -> Result<Wallet> {
+        Ok(Wallet { address: parse_wallet(&self.wallet)? })
+    }
+
+    async fn signature(&self) -> &str {
+        &self.transfer.signature
+    }
+
+    async fn timestamp(&self) -> DateTime<Utc> {
+        self.transfer.timestamp
+    }
+
+    async fn slot(&self) -> u64 {
+        self.transfer.slot
+    }
+
+    async fn instruction_index(&self) -> Option<usize> {
+        self.transfer.instruction_index
+    }
+
+    async fn direction(&self) -> Direction {
+        if self.transfer.is_incoming { Direction::In } else { Direction::Out }
+    }
+
+    async fn amount(&self) -> f64 {
+        self.transfer.amount
+    }
+
+    // In base units (6 decimals)
+    async fn raw_amount(&self) -> u64 {
+        self.transfer.raw_amount
+    }
+
+    async fn from_address(&self) -> &str {
+        &self.transfer.from_address
+    }
+
+    async fn to_address(&self) -> &str {
+        &self.transfer.to_address
+    }
+
+    async fn from_label(&self) -> Option<&str> {
+        self.transfer.from_label.as_deref()
+    }
+
+    async fn to_label(&self) -> Option<&str> {
+        self.transfer.to_label.as_deref()
+    }
+
+    // The other side of the transfer from the wallet's point of view
+    async fn counterparty(&self) -> Counterparty {
+        Counterparty { address: addressbook::counterparty(&self.transfer).0.to_string() }
+    }
+
+    async fn is_internal(&self) -> bool {
+        self.transfer.is_internal
+    }
+
+    async fn categories(&self) -> Vec<String> {
+        categorize(&self.transfer).iter().map(|c| c.as_str().to_string()).collect()
+    }
+
+    async fn program_path(&self) -> &[String] {
+        &self.transfer.program_path
+    }
+
+    // Why the transaction failed, for failed attempts
+    async fn error(&self) -> Option<String> {
+        self.transfer.error.as_ref().map(|e| e.describe())
+    }
+
+    async fn balance_after(&self) -> Option<f64> {
+        self.transfer.balance_after.map(|b| ui_amount(b as i128))
+    }
+
+    #[graphql(complexity = "RPC_COST + child_complexity")]
+    async fn transaction(&self, ctx: &Context<'_>) -> Result<Option<TransactionDetails>> {
+        Ok(state(ctx).transaction_details(&self.transfer.signature).await?)
+    }
+}
+
+#[Object(name = "Transaction")]
+impl TransactionDetails {
+    async fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    async fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    async fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
+    async fn fee_lamports(&self) -> Option<u64> {
+        self.fee_lamports
+    }
+
+    async fn fee_payer(&self) -> Option<&str> {
+        self.fee_payer.as_deref()
+    }
+
+    async fn error(&self) -> Option<String> {
+        self.error.as_ref().map(|e| e.describe())
+    }
+
+    // USDC transfers of every indexed wallet the transaction touched
+    async fn transfers(&self) -> &[IndexedTransfer] {
+        &self.transfers
+    }
+
+    // Everyone the indexed wallets sent USDC to or received it from
+    async fn counterparties(&self) -> Vec<Counterparty> {
+        let addresses: BTreeSet<&str> = self.transfers.iter().map(|t| addressbook::counterparty(&t.transfer).0).collect();
+        addresses.into_iter().map(|address| Counterparty { address: address.to_string() }).collect()
+    }
+}
+
+pub struct Counterparty {
+    address: String,
+}
+
+impl Counterparty {
+    fn book<'a>(&self, ctx: &Context<'a>) -> &'a AddressBook {
+        &state(ctx).address_book
+    }
+}
+
+#[Object]
+impl Counterparty {
+    async fn address(&self) -> &str {
+        &self.address
+    }
+
+    async fn label(&self, ctx: &Context<'_>) -> Option<String> {
+        self.book(ctx).label(&self.address).map(str::to_string)
+    }
+
+    async fn entity_type(&self, ctx: &Context<'_>) -> Option<EntityType> {
+        self.book(ctx).get(&self.address).map(|entry| entry.entity_type.into())
+    }
+}
+
+#[Object]
+impl CounterpartyStats {
+    async fn counterparty(&self) -> Counterparty {
+        Counterparty { address: self.address.clone() }
+    }
+
+    // Another wallet of the same group
+    async fn is_internal(&self) -> bool {
+        self.is_internal
+    }
+
+    async fn total_in(&self) -> f64 {
+        self.total_in
+    }
+
+    async fn total_out(&self) -> f64 {
+        self.total_out
+    }
+
+    async fn net(&self) -> f64 {
+        self.net
+    }
+
+    async fn transfers_in(&self) -> usize {
+        self.transfers_in
+    }
+
+    async fn transfers_out(&self) -> usize {
+        self.transfers_out
+    }
+
+    async fn transactions(&self) -> usize {
+        self.transactions
+    }
+
+    async fn first_seen(&self) -> DateTime<Utc> {
+        self.first_seen
+    }
+
+    async fn last_seen(&self) -> DateTime<Utc> {
+        self.last_seen
+    }
+}
+
+#[Object(name = "Balance")]
+impl BalanceAt {
+    async fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    async fn raw_balance(&self) -> u64 {
+        self.raw_balance
+    }
+
+    // Last transaction at or before the point, if the balance comes from one
+    #[graphql(complexity = "RPC_COST + child_complexity")]
+    async fn transaction(&self, ctx: &Context<'_>) -> Result<Option<TransactionDetails>> {
+        match &self.signature {
+            Some(signature) => Ok(state(ctx).transaction_details(signature).await?),
+            None => Ok(None),
+        }
+    }
+
+    async fn slot(&self) -> Option<u64> {
+        self.slot
+    }
+
+    async fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    // Transfers as they are written to the store by indexing runs, optionally
+    // only those of one wallet
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        direction: Option<Direction>,
+        min_amount: Option<f64>,
+    ) -> Result<impl Stream<Item = IndexedTransfer>> {
+        let state = state(ctx);
+        if state.store.is_none() {
+            return Err(Error::new("Subscriptions need the server to run with --db"));
+        }
+        if let Some(wallet) = &wallet {
+            parse_wallet(wallet)?;
+        }
+        let filter = TransferFilter {
+            direction: direction.map(Into::into),
+            min_amount,
+            ..Default::default()
+        };
+
+        let feed = futures::stream::unfold(state.feed.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(transfer) => return Some((transfer, receiver)),
+                    Err(RecvError::Lagged(missed)) => warn!(missed, "Subscriber fell behind the transfer feed"),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(feed.filter(move |t| {
+            future::ready(wallet.as_ref().is_none_or(|w| *w == t.wallet) && filter.matches(&t.transfer))
+        }))
+    }
+}
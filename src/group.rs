@@ -0,0 +1,94 @@
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::types::*;
+
+// A set of wallets that belong to the same entity
+#[derive(Clone)]
+pub struct WalletGroup {
+    members: HashSet<String>,
+}
+
+// Activity of one wallet within a group
+#[derive(Debug, Serialize)]
+pub struct WalletTransfers {
+    pub wallet: String,
+    #[serde(flatten)]
+    pub activity: WalletActivity,
+}
+
+// Group-level totals with internal movements netted out
+#[derive(Debug, Default, Serialize)]
+pub struct GroupSummary {
+    pub wallet_count: usize,
+    pub external_received: f64,
+    pub external_sent: f64,
+    pub net_change: f64,
+    pub internal_volume: f64,
+    pub internal_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupReport {
+    pub wallets: Vec<WalletTransfers>,
+    pub summary: GroupSummary,
+}
+
+impl WalletGroup {
+    pub fn new(wallets: &[Pubkey]) -> Self {
+        Self {
+            members: wallets.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.members.contains(address)
+    }
+
+    // A transfer is internal when both sides are wallets of the group
+    pub fn is_internal(&self, transfer: &UsdcTransfer) -> bool {
+        self.contains(&transfer.from_address) && self.contains(&transfer.to_address)
+    }
+
+    pub fn mark_internal(&self, transfers: &mut [UsdcTransfer]) {
+        for transfer in transfers.iter_mut() {
+            transfer.is_internal = self.is_internal(transfer);
+        }
+    }
+
+    pub fn build_report(&self, per_wallet: BTreeMap<String, WalletActivity>) -> GroupReport {
+        let mut summary = GroupSummary {
+            wallet_count: per_wallet.len(),
+            ..Default::default()
+        };
+
+        let wallets = per_wallet
+            .into_iter()
+            .map(|(wallet, mut activity)| {
+                self.mark_internal(&mut activity.transfers);
+
+                for transfer in activity.transfers.iter().filter(|t| t.moved_funds()) {
+                    if transfer.is_internal {
+                        // Each internal movement shows up once as SENT in the source
+                        // wallet, so count it from that side only
+                        if !transfer.is_incoming {
+                            summary.internal_volume += transfer.amount;
+                            summary.internal_count += 1;
+                        }
+                    } else if transfer.is_incoming {
+                        summary.external_received += transfer.amount;
+                    } else {
+                        summary.external_sent += transfer.amount;
+                    }
+                }
+
+                WalletTransfers { wallet, activity }
+            })
+            .collect();
+
+        summary.net_change = summary.external_received - summary.external_sent;
+
+        GroupReport { wallets, summary }
+    }
+}
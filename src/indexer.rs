@@ -1,15 +1,41 @@
 use anyhow::{anyhow, Result};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use reqwest::Client;
 use serde_json::json;
-use std::{collections::HashSet, time::Duration as StdDuration};
-
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    str::FromStr,
+    time::Duration as StdDuration,
+};
+use tracing::{debug, info, warn};
+
+use crate::addressbook::AddressBook;
+use crate::balance::{fill_balances, AccountBalances};
+use crate::checkpoint::Checkpoint;
+use crate::delegation::DelegationTracker;
+use crate::errors::TransactionError;
+use crate::group::WalletGroup;
 use crate::types::*;
 use crate::parser::TransactionParser;
+use crate::sink::TransferSink;
+use crate::trace::TransactionTrace;
+
+// Number of transactions fetched concurrently per batch
+const BATCH_SIZE: usize = 10;
+
+// Maximum signatures returned by one getSignaturesForAddress call
+const SIGNATURE_PAGE_SIZE: usize = 1000;
 
 pub struct SolanaIndexer {
     http_client: Client,
     rpc_url: String,
+    include_failed: bool,
+    include_traces: bool,
+    // Look up each transaction's position in its block (one getBlock per slot)
+    block_positions: bool,
+    group: Option<WalletGroup>,
+    address_book: Option<AddressBook>,
 }
 
 impl SolanaIndexer {
@@ -24,95 +50,402 @@ impl SolanaIndexer {
         Ok(Self {
             http_client,
             rpc_url,
+            include_failed: false,
+            include_traces: false,
+            block_positions: true,
+            group: None,
+            address_book: None,
         })
     }
 
-    pub async fn get_usdc_transfers(
+    // Also report transfer attempts from failed transactions
+    pub fn with_failed_transactions(mut self, include_failed: bool) -> Self {
+        self.include_failed = include_failed;
+        self
+    }
+
+    // Also collect the full program invocation tree of each transaction
+    pub fn with_traces(mut self, include_traces: bool) -> Self {
+        self.include_traces = include_traces;
+        self
+    }
+
+    // Order transactions within a slot by their position in the block, at
+    // the cost of one getBlock call per distinct slot
+    pub fn with_block_positions(mut self, block_positions: bool) -> Self {
+        self.block_positions = block_positions;
+        self
+    }
+
+    // Mark transfers between wallets of the group as internal
+    pub fn with_group(mut self, group: WalletGroup) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    // Label both sides of every transfer from the address book
+    pub fn with_address_book(mut self, address_book: AddressBook) -> Self {
+        self.address_book = Some(address_book);
+        self
+    }
+
+    // Index a wallet, handing each processed batch of transfers to the sink
+    // as it is parsed. With a checkpoint, every signature newer than it is
+    // fetched instead of the last `hours_back` hours.
+    pub async fn get_wallet_activity(
         &self,
-        wallet: &str,
+        wallet: Pubkey,
         hours_back: u64,
-    ) -> Result<Vec<UsdcTransfer>> {
+        checkpoint: Option<&Checkpoint>,
+        sink: &mut dyn TransferSink,
+    ) -> Result<WalletActivity> {
+        let wallet_address = wallet.to_string();
         let cutoff_time = Utc::now() - Duration::hours(hours_back as i64);
-        let mut all_transfers = Vec::new();
-        let mut processed_signatures = HashSet::new();
 
-        println!("🔍 Fetching transaction signatures for wallet...");
+        // Taken before the signatures, so every listed transaction up to its
+        // slot is reflected in it
+        let balances = match self.get_usdc_balances(&wallet).await {
+            Ok((slot, accounts)) => Some(AccountBalances::new(slot, accounts)),
+            Err(e) => {
+                warn!(error = %e, "Error fetching USDC balances; balances after transfers are unknown");
+                None
+            }
+        };
+
+        info!(wallet = %wallet_address, "Fetching transaction signatures");
+
+        let mut signatures = match checkpoint {
+            Some(checkpoint) => {
+                info!(slot = checkpoint.slot, signature = %checkpoint.signature, "Resuming after checkpoint");
+                self.get_signatures_until(&wallet, &checkpoint.signature).await?
+            }
+            None => self.get_signatures_for_address(&wallet, None, None).await?,
+        };
 
-        // Get all signatures for the wallet
-        let signatures = self.get_signatures_for_address(wallet).await?;
-        
-        println!("📝 Found {} recent signatures", signatures.len());
+        info!(signatures = signatures.len(), "Found recent signatures");
 
-        // Filter signatures by time
+        // Signatures come newest first; the newest one becomes the next checkpoint
+        let next_checkpoint = signatures
+            .first()
+            .map(|sig| Checkpoint {
+                signature: sig.signature.clone(),
+                slot: sig.slot,
+            })
+            .or_else(|| checkpoint.cloned());
+
+        self.fill_block_times(&mut signatures).await;
+
+        // Filter signatures by time; everything after a checkpoint is new, however old
         let recent_signatures: Vec<_> = signatures
             .into_iter()
             .filter(|sig| {
-                if let Some(block_time) = sig.block_time {
-                    let tx_time = chrono::Utc.timestamp_opt(block_time, 0).single().unwrap_or(Utc::now());
-                    tx_time >= cutoff_time
-                } else {
-                    true // Include transactions without block time
+                match sig.block_time.and_then(|bt| Utc.timestamp_opt(bt, 0).single()) {
+                    Some(tx_time) => checkpoint.is_some() || tx_time >= cutoff_time,
+                    None => {
+                        // Keep it rather than guess; parsing reports the missing time
+                        warn!(signature = %sig.signature, slot = sig.slot, "No block time");
+                        true
+                    }
                 }
             })
             .collect();
 
-        println!("⏰ {} signatures within {} hour window", recent_signatures.len(), hours_back);
+        if checkpoint.is_some() {
+            info!(signatures = recent_signatures.len(), "Signatures since checkpoint");
+        } else {
+            info!(signatures = recent_signatures.len(), hours = hours_back, "Signatures within window");
+        }
 
-        // Process transactions sequentially
-        for (i, sig) in recent_signatures.iter().enumerate() {
-            if processed_signatures.contains(&sig.signature) {
-                continue;
-            }
+        sink.begin(&wallet_address, hours_back).await?;
+        let mut activity = self.process_signatures(wallet, &recent_signatures, balances, sink).await?;
+        sink.finish(&wallet_address, activity.transfers.len()).await?;
 
-            processed_signatures.insert(sig.signature.clone());
+        activity.checkpoint = next_checkpoint;
+        Ok(activity)
+    }
 
-            if i % 10 == 0 {
-                println!("🔄 Processing transaction {}/{}", i + 1, recent_signatures.len());
-            }
+    // Fetch and parse the given signatures in batches, handing each batch of
+    // transfers to the sink as it is parsed. Balances after each transaction
+    // are walked back from a snapshot of the wallet's balances, if given;
+    // transactions newer than the snapshot get none.
+    pub async fn process_signatures(
+        &self,
+        wallet: Pubkey,
+        signatures: &[GetSignaturesForAddressResponse],
+        mut balances: Option<AccountBalances>,
+        sink: &mut dyn TransferSink,
+    ) -> Result<WalletActivity> {
+        let wallet_address = wallet.to_string();
+        let mut all_transfers = Vec::new();
+        let mut all_delegations = Vec::new();
+        let mut all_lifecycle = Vec::new();
+        let mut all_traces = Vec::new();
+        let mut processed_signatures = HashSet::new();
+        let mut skipped = 0;
+
+        // Failed transactions are only fetched when asked to
+        let signatures: Vec<_> = signatures
+            .iter()
+            .filter(|sig| self.include_failed || sig.err.is_none())
+            .collect();
+
+        let finality: HashMap<&str, Finality> = signatures
+            .iter()
+            .filter_map(|sig| Some((sig.signature.as_str(), sig.confirmation_status?)))
+            .collect();
+        let mut block_signatures: HashMap<u64, Vec<String>> = HashMap::new();
+
+        // Process transactions in batches
+        for (batch_index, batch) in signatures.chunks(BATCH_SIZE).enumerate() {
+            debug!(
+                batch = batch_index + 1,
+                batches = signatures.len().div_ceil(BATCH_SIZE),
+                "Processing batch"
+            );
+
+            let batch_futures: Vec<_> = batch
+                .iter()
+                .filter(|sig| processed_signatures.insert(sig.signature.clone()))
+                .map(|sig| self.get_transaction(&sig.signature))
+                .collect();
+
+            let batch_results = futures::future::join_all(batch_futures).await;
+            let mut batch_transfers = Vec::new();
 
-            match self.get_transaction(&sig.signature).await {
-                Ok(Some(transaction)) => {
-                    match TransactionParser::parse_usdc_transfers(&transaction, wallet) {
-                        Ok(mut transfers) => {
-                            all_transfers.append(&mut transfers);
+            for result in batch_results {
+                match result {
+                    Ok(Some(mut transaction)) => {
+                        if transaction.block_time.is_none() {
+                            transaction.block_time = self.lookup_block_time(transaction.slot).await;
                         }
-                        Err(e) => {
-                            eprintln!("⚠️ Error parsing transaction {}: {}", sig.signature, e);
+
+                        let signature = transaction.transaction.signatures.first().cloned().unwrap_or_default();
+                        let tx_index = self.get_tx_index(&mut block_signatures, transaction.slot, &signature).await;
+                        let tx_finality = finality.get(signature.as_str()).copied();
+                        let touched = TransactionParser::wallet_usdc_account_balances(&transaction, &wallet);
+                        let balance_after = balances.as_mut().and_then(|b| b.step_back(transaction.slot, &touched));
+                        let mut parsed = true;
+
+                        match TransactionParser::parse_usdc_transfers(&transaction, &wallet) {
+                            Ok(transfers) => {
+                                batch_transfers.extend(transfers.into_iter().map(|t| UsdcTransfer {
+                                    tx_index,
+                                    finality: tx_finality,
+                                    balance_after,
+                                    ..t
+                                }));
+                            }
+                            Err(e) => {
+                                warn!(error = %e, "Error parsing transaction");
+                                parsed = false;
+                            }
+                        }
+
+                        if self.include_failed {
+                            match TransactionParser::parse_failed_transfers(&transaction, &wallet) {
+                                Ok(transfers) => {
+                                    batch_transfers.extend(transfers.into_iter().map(|t| UsdcTransfer {
+                                        tx_index,
+                                        finality: tx_finality,
+                                        balance_after,
+                                        ..t
+                                    }));
+                                }
+                                Err(e) => {
+                                    warn!(error = %e, "Error parsing failed transaction");
+                                    parsed = false;
+                                }
+                            }
+                        }
+
+                        match TransactionParser::parse_delegation_events(&transaction, &wallet) {
+                            Ok(events) => {
+                                all_delegations.extend(events.into_iter().map(|e| DelegationEvent { tx_index, ..e }));
+                            }
+                            Err(e) => {
+                                warn!(error = %e, "Error parsing delegations");
+                                parsed = false;
+                            }
+                        }
+
+                        match TransactionParser::parse_lifecycle_events(&transaction, &wallet) {
+                            Ok(events) => {
+                                all_lifecycle.extend(events.into_iter().map(|e| LifecycleEvent { tx_index, ..e }));
+                            }
+                            Err(e) => {
+                                warn!(error = %e, "Error parsing account lifecycle");
+                                parsed = false;
+                            }
+                        }
+
+                        if self.include_traces {
+                            all_traces.push(TransactionTrace::build(&transaction));
+                        }
+                        if !parsed {
+                            skipped += 1;
+                        }
+                    }
+                    Ok(None) => {
+                        // Not (yet) available from the node
+                        warn!("Transaction not found");
+                        skipped += 1;
+                        if let Some(balances) = balances.as_mut() {
+                            balances.lose_track();
+                        }
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Error fetching transaction");
+                        skipped += 1;
+                        if let Some(balances) = balances.as_mut() {
+                            balances.lose_track();
                         }
                     }
                 }
-                Ok(None) => {
-                    // Transaction not found or null
-                }
-                Err(e) => {
-                    eprintln!("⚠️ Error fetching transaction {}: {}", sig.signature, e);
-                }
             }
 
-            // Small delay between requests
+            if let Some(group) = &self.group {
+                group.mark_internal(&mut batch_transfers);
+            }
+            if let Some(address_book) = &self.address_book {
+                address_book.label_transfers(&mut batch_transfers);
+            }
+            if !batch_transfers.is_empty() {
+                sink.write(&wallet_address, &batch_transfers).await?;
+            }
+            all_transfers.extend(batch_transfers);
+
+            // Small delay between batches to be respectful to RPC
             tokio::time::sleep(StdDuration::from_millis(100)).await;
         }
 
-        // Sort transfers by timestamp (newest first)
-        all_transfers.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        // Sort transfers by chain position (newest first), keeping instruction
+        // order within a transaction
+        all_transfers.sort_by(|a, b| {
+            (b.slot, b.tx_index)
+                .cmp(&(a.slot, a.tx_index))
+                .then_with(|| a.signature.cmp(&b.signature))
+                .then_with(|| (a.instruction_index, a.inner_index).cmp(&(b.instruction_index, b.inner_index)))
+        });
 
-        println!("✅ Found {} USDC transfers", all_transfers.len());
+        // Replay approvals and delegated spends to get allowances over time
+        let outstanding_delegations = DelegationTracker::new().replay(&mut all_delegations);
 
-        Ok(all_transfers)
+        all_lifecycle.sort_by(|a, b| {
+            (b.slot, b.tx_index)
+                .cmp(&(a.slot, a.tx_index))
+                .then_with(|| a.signature.cmp(&b.signature))
+                .then_with(|| a.instruction_index.cmp(&b.instruction_index))
+        });
+
+        info!(wallet = %wallet_address, transfers = all_transfers.len(), "Found USDC transfers");
+
+        let mut activity = WalletActivity {
+            transfers: all_transfers,
+            delegations: all_delegations,
+            outstanding_delegations,
+            lifecycle: all_lifecycle,
+            traces: all_traces,
+            skipped,
+            ..Default::default()
+        };
+        fill_balances(&mut activity);
+        Ok(activity)
     }
 
-    async fn get_signatures_for_address(&self, address: &str) -> Result<Vec<GetSignaturesForAddressResponse>> {
+    // A transaction with the USDC transfers of each wallet owning one of its
+    // USDC token accounts, or None if the RPC node does not know it
+    pub async fn get_transaction_details(&self, signature: &str) -> Result<Option<TransactionDetails>> {
+        let Some(mut transaction) = self.get_transaction(signature).await? else {
+            return Ok(None);
+        };
+        if transaction.block_time.is_none() {
+            transaction.block_time = self.lookup_block_time(transaction.slot).await;
+        }
+
+        let meta = transaction.meta.as_ref();
+        let owners: BTreeSet<&str> = meta
+            .into_iter()
+            .flat_map(|m| m.pre_token_balances.iter().chain(m.post_token_balances.iter()).flatten())
+            .filter(|balance| balance.mint == USDC_MINT)
+            .filter_map(|balance| balance.owner.as_deref())
+            .collect();
+
+        let mut transfers = Vec::new();
+        for owner in owners {
+            let wallet = Pubkey::from_str(owner)?;
+            let mut parsed = TransactionParser::parse_usdc_transfers(&transaction, &wallet)?;
+            if self.include_failed {
+                parsed.extend(TransactionParser::parse_failed_transfers(&transaction, &wallet)?);
+            }
+            if let Some(group) = &self.group {
+                group.mark_internal(&mut parsed);
+            }
+            if let Some(address_book) = &self.address_book {
+                address_book.label_transfers(&mut parsed);
+            }
+            transfers.extend(parsed.into_iter().map(|transfer| IndexedTransfer {
+                wallet: owner.to_string(),
+                transfer,
+            }));
+        }
+
+        Ok(Some(TransactionDetails {
+            signature: signature.to_string(),
+            slot: transaction.slot,
+            timestamp: transaction.block_time.and_then(|t| Utc.timestamp_opt(t, 0).single()),
+            fee_lamports: meta.map(|m| m.fee),
+            fee_payer: transaction.transaction.message.account_keys.first().cloned(),
+            error: meta.and_then(|m| Some(TransactionError::decode(m.err.as_ref()?, m.log_messages.as_deref()))),
+            transfers,
+            trace: self.include_traces.then(|| TransactionTrace::build(&transaction)),
+        }))
+    }
+
+    // Position of a transaction within its block, from a per-run cache of block signatures
+    async fn get_tx_index(
+        &self,
+        cache: &mut HashMap<u64, Vec<String>>,
+        slot: u64,
+        signature: &str,
+    ) -> Option<u32> {
+        if !self.block_positions {
+            return None;
+        }
+        if let Entry::Vacant(entry) = cache.entry(slot) {
+            match self.get_block_signatures(slot).await {
+                Ok(signatures) => {
+                    entry.insert(signatures);
+                }
+                Err(e) => {
+                    warn!(slot, error = %e, "Error fetching block");
+                    return None;
+                }
+            }
+        }
+
+        cache.get(&slot)?
+            .iter()
+            .position(|s| s == signature)
+            .map(|i| i as u32)
+    }
+
+    // Block time of a slot, logging rather than failing when the node errors
+    async fn lookup_block_time(&self, slot: u64) -> Option<i64> {
+        match self.get_block_time(slot).await {
+            Ok(block_time) => block_time,
+            Err(e) => {
+                warn!(slot, error = %e, "Error fetching block time");
+                None
+            }
+        }
+    }
+
+    async fn get_block_time(&self, slot: u64) -> Result<Option<i64>> {
         let request = json!({
             "jsonrpc": "2.0",
             "id": 1,
-            "method": "getSignaturesForAddress",
-            "params": [
-                address,
-                {
-                    "limit": 1000,
-                    "commitment": "confirmed"
-                }
-            ]
+            "method": "getBlockTime",
+            "params": [slot]
         });
 
         let response = self.http_client
@@ -121,19 +454,20 @@ impl SolanaIndexer {
             .send()
             .await?;
 
-        let rpc_response: RpcResponse<Vec<GetSignaturesForAddressResponse>> = response.json().await?;
+        let rpc_response: RpcResponse<Option<i64>> = response.json().await?;
         Ok(rpc_response.result)
     }
 
-    async fn get_transaction(&self, signature: &str) -> Result<Option<TransactionResponse>> {
+    async fn get_block_signatures(&self, slot: u64) -> Result<Vec<String>> {
         let request = json!({
             "jsonrpc": "2.0",
             "id": 1,
-            "method": "getTransaction",
+            "method": "getBlock",
             "params": [
-                signature,
+                slot,
                 {
-                    "encoding": "json",
+                    "transactionDetails": "signatures",
+                    "rewards": false,
                     "commitment": "confirmed",
                     "maxSupportedTransactionVersion": 0
                 }
@@ -146,65 +480,191 @@ impl SolanaIndexer {
             .send()
             .await?;
 
-        let rpc_response: RpcResponse<Option<TransactionResponse>> = response.json().await?;
-        Ok(rpc_response.result)
+        let rpc_response: RpcResponse<Option<BlockSignaturesResponse>> = response.json().await?;
+        Ok(rpc_response.result.map(|block| block.signatures).unwrap_or_default())
     }
-} !processed_signatures.contains(&sig.signature))
-                .map(|sig| {
-                    processed_signatures.insert(sig.signature.clone());
-                    self.get_transaction(&sig.signature)
-                })
-                .collect();
 
-            let batch_results = futures::future::join_all(batch_futures).await;
+    // Look up block times the signature list did not include
+    async fn fill_block_times(&self, signatures: &mut [GetSignaturesForAddressResponse]) {
+        for sig in signatures.iter_mut().filter(|sig| sig.block_time.is_none()) {
+            sig.block_time = self.lookup_block_time(sig.slot).await;
+        }
+    }
 
-            for result in batch_results {
-                match result {
-                    Ok(Some(transaction)) => {
-                        match TransactionParser::parse_usdc_transfers(&transaction, &wallet) {
-                            Ok(mut transfers) => {
-                                all_transfers.append(&mut transfers);
-                            }
-                            Err(e) => {
-                                eprintln!("⚠️ Error parsing transaction: {}", e);
-                            }
-                        }
-                    }
-                    Ok(None) => {
-                        // Transaction not found or null
-                    }
-                    Err(e) => {
-                        eprintln!("⚠️ Error fetching transaction: {}", e);
-                    }
-                }
-            }
+    // One page of signatures with block times in [start, end), newest first,
+    // starting before the `before` signature. Also returns the cursor of the
+    // next page, or None once the page reaches past `start`. Signatures whose
+    // block time is unknown are kept.
+    pub async fn get_signature_page_between(
+        &self,
+        address: &Pubkey,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        before: Option<&str>,
+    ) -> Result<(Vec<GetSignaturesForAddressResponse>, Option<String>)> {
+        let (start, end) = (start.timestamp(), end.timestamp());
+
+        let mut page = self.get_signatures_for_address(address, before, None).await?;
+        let done = page.len() < SIGNATURE_PAGE_SIZE;
+        let next = page.last().map(|sig| sig.signature.clone());
+
+        self.fill_block_times(&mut page).await;
+        let reached_start = page.iter().any(|sig| sig.block_time.is_some_and(|t| t < start));
+        let signatures = page
+            .into_iter()
+            .filter(|sig| sig.block_time.is_none_or(|t| t >= start && t < end))
+            .collect();
 
-            // Small delay between batches to be respectful to RPC
-            tokio::time::sleep(StdDuration::from_millis(100)).await;
+        Ok((signatures, next.filter(|_| !done && !reached_start)))
+    }
+
+    // Whether the RPC node reports itself healthy
+    pub async fn get_health(&self) -> Result<()> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getHealth"
+        });
+
+        let response = self.http_client
+            .post(&self.rpc_url)
+            .json(&request)
+            .send()
+            .await?;
+
+        let body: serde_json::Value = response.json().await?;
+        match body.get("result").and_then(|r| r.as_str()) {
+            Some("ok") => Ok(()),
+            _ => Err(anyhow!("RPC node is unhealthy: {}", body.get("error").unwrap_or(&body))),
         }
+    }
 
-        // Sort transfers by timestamp (newest first)
-        all_transfers.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    // Balances in base units of all USDC token accounts owned by a wallet, and
+    // the slot they were read at
+    pub async fn get_usdc_balances(&self, owner: &Pubkey) -> Result<(u64, HashMap<String, u64>)> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getTokenAccountsByOwner",
+            "params": [
+                owner.to_string(),
+                { "mint": USDC_MINT },
+                { "encoding": "jsonParsed", "commitment": "confirmed" }
+            ]
+        });
 
-        println!("✅ Found {} USDC transfers", all_transfers.len());
+        let response = self.http_client
+            .post(&self.rpc_url)
+            .json(&request)
+            .send()
+            .await?;
 
-        Ok(all_transfers)
+        let rpc_response: RpcResponse<RpcContextResponse<Vec<serde_json::Value>>> = response.json().await?;
+        let mut balances = HashMap::new();
+        for account in &rpc_response.result.value {
+            let address = account["pubkey"].as_str().unwrap_or_default();
+            let amount = account
+                .pointer("/account/data/parsed/info/tokenAmount/amount")
+                .and_then(|amount| amount.as_str()?.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid balance for token account {}", address))?;
+            balances.insert(address.to_string(), amount);
+        }
+        Ok((rpc_response.result.context.slot, balances))
     }
 
-    async fn get_signatures_for_address(&self, address: &Pubkey) -> Result<Vec<GetSignaturesForAddressResponse>> {
+    // Addresses of the USDC token accounts owned by a wallet
+    pub async fn get_usdc_accounts(&self, owner: &Pubkey) -> Result<Vec<String>> {
         let request = json!({
             "jsonrpc": "2.0",
             "id": 1,
-            "method": "getSignaturesForAddress",
+            "method": "getTokenAccountsByOwner",
             "params": [
-                address.to_string(),
-                {
-                    "limit": 1000,
-                    "commitment": "confirmed"
-                }
+                owner.to_string(),
+                { "mint": USDC_MINT },
+                { "encoding": "base64", "commitment": "confirmed" }
             ]
         });
 
+        let response = self.http_client
+            .post(&self.rpc_url)
+            .json(&request)
+            .send()
+            .await?;
+
+        let rpc_response: RpcResponse<RpcContextResponse<Vec<KeyedAccount>>> = response.json().await?;
+        Ok(rpc_response.result.value.into_iter().map(|account| account.pubkey).collect())
+    }
+
+    // Current balance of a token account in base units
+    pub async fn get_token_account_balance(&self, account: &str) -> Result<u64> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getTokenAccountBalance",
+            "params": [account, { "commitment": "confirmed" }]
+        });
+
+        let response = self.http_client
+            .post(&self.rpc_url)
+            .json(&request)
+            .send()
+            .await?;
+
+        let rpc_response: RpcResponse<RpcContextResponse<UiTokenAmount>> = response.json().await?;
+        rpc_response
+            .result
+            .value
+            .amount
+            .parse()
+            .map_err(|e| anyhow!("Invalid balance for token account {}: {}", account, e))
+    }
+
+    // All signatures newer than `until`, paging back with `before`
+    async fn get_signatures_until(
+        &self,
+        address: &Pubkey,
+        until: &str,
+    ) -> Result<Vec<GetSignaturesForAddressResponse>> {
+        let mut signatures = Vec::new();
+
+        loop {
+            let before = signatures
+                .last()
+                .map(|sig: &GetSignaturesForAddressResponse| sig.signature.clone());
+            let page = self.get_signatures_for_address(address, before.as_deref(), Some(until)).await?;
+            let done = page.len() < SIGNATURE_PAGE_SIZE;
+            signatures.extend(page);
+
+            if done {
+                return Ok(signatures);
+            }
+        }
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        before: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<GetSignaturesForAddressResponse>> {
+        let mut config = json!({
+            "limit": SIGNATURE_PAGE_SIZE,
+            "commitment": "confirmed"
+        });
+        if let Some(before) = before {
+            config["before"] = json!(before);
+        }
+        if let Some(until) = until {
+            config["until"] = json!(until);
+        }
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getSignaturesForAddress",
+            "params": [address.to_string(), config]
+        });
+
         let response = self.http_client
             .post(&self.rpc_url)
             .json(&request)
@@ -240,83 +700,3 @@ impl SolanaIndexer {
         Ok(rpc_response.result)
     }
 }
-
-// Add futures dependency for join_all
-use futures;
-
-impl SolanaIndexer {
-    // Alternative implementation without futures dependency
-    pub async fn get_usdc_transfers_sequential(
-        &self,
-        wallet: Pubkey,
-        hours_back: u64,
-    ) -> Result<Vec<UsdcTransfer>> {
-        let cutoff_time = Utc::now() - Duration::hours(hours_back as i64);
-        let mut all_transfers = Vec::new();
-        let mut processed_signatures = HashSet::new();
-
-        println!("🔍 Fetching transaction signatures for wallet...");
-
-        // Get all signatures for the wallet
-        let signatures = self.get_signatures_for_address(&wallet).await?;
-        
-        println!("📝 Found {} recent signatures", signatures.len());
-
-        // Filter signatures by time
-        let recent_signatures: Vec<_> = signatures
-            .into_iter()
-            .filter(|sig| {
-                if let Some(block_time) = sig.block_time {
-                    let tx_time = chrono::Utc.timestamp_opt(block_time, 0).single().unwrap_or(Utc::now());
-                    tx_time >= cutoff_time
-                } else {
-                    true // Include transactions without block time
-                }
-            })
-            .collect();
-
-        println!("⏰ {} signatures within {} hour window", recent_signatures.len(), hours_back);
-
-        // Process transactions sequentially
-        for (i, sig) in recent_signatures.iter().enumerate() {
-            if processed_signatures.contains(&sig.signature) {
-                continue;
-            }
-
-            processed_signatures.insert(sig.signature.clone());
-
-            if i % 10 == 0 {
-                println!("🔄 Processing transaction {}/{}", i + 1, recent_signatures.len());
-            }
-
-            match self.get_transaction(&sig.signature).await {
-                Ok(Some(transaction)) => {
-                    match TransactionParser::parse_usdc_transfers(&transaction, &wallet) {
-                        Ok(mut transfers) => {
-                            all_transfers.append(&mut transfers);
-                        }
-                        Err(e) => {
-                            eprintln!("⚠️ Error parsing transaction {}: {}", sig.signature, e);
-                        }
-                    }
-                }
-                Ok(None) => {
-                    // Transaction not found or null
-                }
-                Err(e) => {
-                    eprintln!("⚠️ Error fetching transaction {}: {}", sig.signature, e);
-                }
-            }
-
-            // Small delay between requests
-            tokio::time::sleep(StdDuration::from_millis(50)).await;
-        }
-
-        // Sort transfers by timestamp (newest first)
-        all_transfers.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-
-        println!("✅ Found {} USDC transfers", all_transfers.len());
-
-        Ok(all_transfers)
-    }
-}
\ No newline at end of file
@@ -0,0 +1,267 @@
+use solana_sdk::program_option::COption;
+use spl_token::instruction::{AuthorityType, TokenInstruction};
+
+use crate::types::*;
+
+// SPL Token instructions the indexer cares about, decoded from raw instruction data
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenOp {
+    Transfer { amount: u64 },
+    TransferChecked { amount: u64, decimals: u8 },
+    Approve { amount: u64 },
+    ApproveChecked { amount: u64, decimals: u8 },
+    Revoke,
+    // Owner is only carried in the data for InitializeAccount2/3
+    InitializeAccount { owner: Option<String> },
+    CloseAccount,
+    FreezeAccount,
+    ThawAccount,
+    SetOwner { new_owner: Option<String> },
+}
+
+// An instruction of any program with its accounts resolved to addresses
+#[derive(Debug, Clone)]
+pub struct RawInstruction {
+    pub instruction_index: usize,
+    // Position among the inner instructions of `instruction_index`, for CPIs
+    pub inner_index: Option<usize>,
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    pub data: Vec<u8>,
+    // 1 for top-level instructions, deeper for CPIs
+    pub stack_height: u32,
+    // Programs from the top-level instruction down to this one
+    pub program_path: Vec<String>,
+}
+
+// A decoded SPL Token instruction with its accounts resolved to addresses
+#[derive(Debug, Clone)]
+pub struct DecodedTokenInstruction {
+    pub instruction_index: usize,
+    pub inner_index: Option<usize>,
+    pub accounts: Vec<String>,
+    pub op: TokenOp,
+    // Programs from the top-level instruction down to the token program
+    pub program_path: Vec<String>,
+}
+
+impl DecodedTokenInstruction {
+    // Source token account of a transfer or approval
+    pub fn source(&self) -> Option<&str> {
+        self.accounts.first().map(String::as_str)
+    }
+
+    // Destination token account of a transfer
+    pub fn destination(&self) -> Option<&str> {
+        match self.op {
+            TokenOp::Transfer { .. } => self.accounts.get(1),
+            TokenOp::TransferChecked { .. } => self.accounts.get(2),
+            _ => None,
+        }
+        .map(String::as_str)
+    }
+
+    // Account that signed the instruction (owner, delegate or freeze authority)
+    pub fn authority(&self) -> Option<&str> {
+        match self.op {
+            TokenOp::Transfer { .. } | TokenOp::Approve { .. } => self.accounts.get(2),
+            TokenOp::TransferChecked { .. } | TokenOp::ApproveChecked { .. } => self.accounts.get(3),
+            TokenOp::Revoke | TokenOp::SetOwner { .. } => self.accounts.get(1),
+            TokenOp::CloseAccount | TokenOp::FreezeAccount | TokenOp::ThawAccount => {
+                self.accounts.get(2)
+            }
+            TokenOp::InitializeAccount { .. } => None,
+        }
+        .map(String::as_str)
+    }
+
+    // Mint of an account being initialized, frozen or thawed
+    pub fn mint(&self) -> Option<&str> {
+        match self.op {
+            TokenOp::InitializeAccount { .. } | TokenOp::FreezeAccount | TokenOp::ThawAccount => {
+                self.accounts.get(1)
+            }
+            _ => None,
+        }
+        .map(String::as_str)
+    }
+
+    // Owner of an account being initialized
+    pub fn initialized_owner(&self) -> Option<&str> {
+        match &self.op {
+            TokenOp::InitializeAccount { owner: Some(owner) } => Some(owner.as_str()),
+            TokenOp::InitializeAccount { owner: None } => self.accounts.get(2).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    // Account receiving the lamports of a closed account
+    pub fn close_destination(&self) -> Option<&str> {
+        match self.op {
+            TokenOp::CloseAccount => self.accounts.get(1).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    // Delegate being approved
+    pub fn delegate(&self) -> Option<&str> {
+        match self.op {
+            TokenOp::Approve { .. } => self.accounts.get(1),
+            TokenOp::ApproveChecked { .. } => self.accounts.get(2),
+            _ => None,
+        }
+        .map(String::as_str)
+    }
+}
+
+pub struct InstructionDecoder;
+
+impl InstructionDecoder {
+    // Full account list: static keys followed by lookup-table addresses
+    pub fn account_keys(tx: &TransactionResponse) -> Vec<String> {
+        let mut keys = tx.transaction.message.account_keys.clone();
+
+        if let Some(loaded) = tx.meta.as_ref().and_then(|m| m.loaded_addresses.as_ref()) {
+            keys.extend(loaded.writable.iter().cloned());
+            keys.extend(loaded.readonly.iter().cloned());
+        }
+
+        keys
+    }
+
+    // All instructions, top-level and inner, in execution order
+    pub fn instructions(tx: &TransactionResponse) -> Vec<RawInstruction> {
+        let account_keys = Self::account_keys(tx);
+        let inner_instructions = tx
+            .meta
+            .as_ref()
+            .and_then(|m| m.inner_instructions.as_ref());
+        let mut resolved = Vec::new();
+
+        for (index, instruction) in tx.transaction.message.instructions.iter().enumerate() {
+            let Some(top_level) = Self::resolve(
+                &account_keys,
+                instruction.program_id_index,
+                &instruction.accounts,
+                &instruction.data,
+                index,
+                1,
+                &[],
+            ) else {
+                continue;
+            };
+
+            let inner = inner_instructions
+                .into_iter()
+                .flatten()
+                .filter(|inner| inner.index as usize == index)
+                .flat_map(|inner| inner.instructions.iter());
+
+            // Path of the most recent instruction, used to find each CPI's caller
+            let mut path = top_level.program_path.clone();
+            resolved.push(top_level);
+
+            for (inner_index, instruction) in inner.enumerate() {
+                // Older nodes omit stackHeight; treat those as direct CPIs
+                let stack_height = instruction.stack_height.unwrap_or(2).max(2);
+                path.truncate(stack_height as usize - 1);
+
+                if let Some(ix) = Self::resolve(
+                    &account_keys,
+                    instruction.program_id_index,
+                    &instruction.accounts,
+                    &instruction.data,
+                    index,
+                    stack_height,
+                    &path,
+                ) {
+                    path = ix.program_path.clone();
+                    resolved.push(RawInstruction { inner_index: Some(inner_index), ..ix });
+                }
+            }
+        }
+
+        resolved
+    }
+
+    // Decode all SPL Token instructions, top-level and inner, in execution order
+    pub fn decode_token_instructions(tx: &TransactionResponse) -> Vec<DecodedTokenInstruction> {
+        Self::instructions(tx)
+            .into_iter()
+            .filter(|ix| ix.program_id == SPL_TOKEN_PROGRAM_ID)
+            .filter_map(|ix| {
+                let op = Self::decode_token_op(&ix.data)?;
+                Some(DecodedTokenInstruction {
+                    instruction_index: ix.instruction_index,
+                    inner_index: ix.inner_index,
+                    accounts: ix.accounts,
+                    op,
+                    program_path: ix.program_path,
+                })
+            })
+            .collect()
+    }
+
+    fn resolve(
+        account_keys: &[String],
+        program_id_index: u8,
+        accounts: &[u8],
+        data: &str,
+        instruction_index: usize,
+        stack_height: u32,
+        caller_path: &[String],
+    ) -> Option<RawInstruction> {
+        let program_id = account_keys.get(program_id_index as usize)?.clone();
+        let mut program_path = caller_path.to_vec();
+        program_path.push(program_id.clone());
+        let data = bs58::decode(data).into_vec().ok()?;
+        let accounts = accounts
+            .iter()
+            .map(|i| account_keys.get(*i as usize).cloned())
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(RawInstruction {
+            instruction_index,
+            inner_index: None,
+            program_id,
+            accounts,
+            data,
+            stack_height,
+            program_path,
+        })
+    }
+
+    fn decode_token_op(data: &[u8]) -> Option<TokenOp> {
+        let op = match TokenInstruction::unpack(data).ok()? {
+            TokenInstruction::Transfer { amount } => TokenOp::Transfer { amount },
+            TokenInstruction::TransferChecked { amount, decimals } => {
+                TokenOp::TransferChecked { amount, decimals }
+            }
+            TokenInstruction::Approve { amount } => TokenOp::Approve { amount },
+            TokenInstruction::ApproveChecked { amount, decimals } => {
+                TokenOp::ApproveChecked { amount, decimals }
+            }
+            TokenInstruction::Revoke => TokenOp::Revoke,
+            TokenInstruction::InitializeAccount => TokenOp::InitializeAccount { owner: None },
+            TokenInstruction::InitializeAccount2 { owner }
+            | TokenInstruction::InitializeAccount3 { owner } => TokenOp::InitializeAccount {
+                owner: Some(owner.to_string()),
+            },
+            TokenInstruction::CloseAccount => TokenOp::CloseAccount,
+            TokenInstruction::FreezeAccount => TokenOp::FreezeAccount,
+            TokenInstruction::ThawAccount => TokenOp::ThawAccount,
+            TokenInstruction::SetAuthority {
+                authority_type: AuthorityType::AccountOwner,
+                new_authority,
+            } => TokenOp::SetOwner {
+                new_owner: match new_authority {
+                    COption::Some(owner) => Some(owner.to_string()),
+                    COption::None => None,
+                },
+            },
+            _ => return None,
+        };
+
+        Some(op)
+    }
+}
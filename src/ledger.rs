@@ -0,0 +1,316 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Write,
+    path::Path,
+};
+use tracing::warn;
+
+use crate::accounting::{format_units, memo, SOL_DECIMALS};
+use crate::balance::{chain_order, opening_balance, signed_amount};
+use crate::sink::TransferSink;
+use crate::types::*;
+
+const FEES_ACCOUNT: &str = "Expenses:Fees:SOL";
+const OPENING_ACCOUNT: &str = "Equity:Opening-Balances";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Beancount,
+    Ledger,
+}
+
+#[derive(Deserialize)]
+struct AccountRow {
+    address: String,
+    account: String,
+}
+
+// Accounts for wallets and counterparties, from a CSV file with
+// `address,account` rows; unmapped wallets get an asset account of their own
+// and unmapped counterparties go to Income/Expenses:Uncategorized
+#[derive(Default)]
+pub struct AccountMap {
+    accounts: HashMap<String, String>,
+}
+
+impl AccountMap {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+
+        let mut accounts = HashMap::new();
+        for row in reader.deserialize() {
+            let row: AccountRow = row.map_err(|e| anyhow!("Invalid account mapping in {}: {}", path.display(), e))?;
+            accounts.insert(row.address, row.account);
+        }
+        Ok(Self { accounts })
+    }
+
+    fn wallet(&self, address: &str) -> String {
+        self.accounts
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| format!("Assets:Solana:Wallet-{}", address))
+    }
+
+    fn counterparty(&self, address: &str, is_incoming: bool) -> String {
+        match self.accounts.get(address) {
+            Some(account) => account.clone(),
+            None if is_incoming => "Income:Uncategorized".to_string(),
+            None => "Expenses:Uncategorized".to_string(),
+        }
+    }
+}
+
+struct Posting {
+    account: String,
+    amount: i128,
+    currency: &'static str,
+    counterparty: Option<String>,
+    // Balance the account must have after this posting (Ledger only)
+    assertion: Option<u64>,
+}
+
+struct Entry {
+    date: NaiveDate,
+    narration: String,
+    signature: Option<String>,
+    postings: Vec<Posting>,
+}
+
+// Writes transfers as a Beancount or Ledger journal once all wallets are
+// done: one entry per transaction with its USDC legs and the SOL fee, the
+// signature as metadata, an opening balance per wallet and balance
+// assertions from the post token balances
+pub struct LedgerSink {
+    writer: Box<dyn Write + Send>,
+    dialect: Dialect,
+    accounts: AccountMap,
+    legs: Vec<(String, UsdcTransfer)>,
+}
+
+impl LedgerSink {
+    pub fn new(writer: Box<dyn Write + Send>, dialect: Dialect, accounts: AccountMap) -> Self {
+        Self {
+            writer,
+            dialect,
+            accounts,
+            legs: Vec::new(),
+        }
+    }
+
+    // Legs grouped per transaction in chain order
+    fn transactions(&mut self) -> Vec<Vec<(String, UsdcTransfer)>> {
+        self.legs.sort_by(|(_, a), (_, b)| chain_order(a, b));
+
+        let mut transactions: Vec<Vec<(String, UsdcTransfer)>> = Vec::new();
+        for leg in self.legs.drain(..) {
+            match transactions.last_mut() {
+                Some(tx) if tx[0].1.signature == leg.1.signature => tx.push(leg),
+                _ => transactions.push(vec![leg]),
+            }
+        }
+        transactions
+    }
+
+    fn entries(&mut self) -> Vec<Entry> {
+        let transactions = self.transactions();
+        let mut entries = Vec::new();
+
+        let mut openings: BTreeMap<String, i128> = BTreeMap::new();
+        let wallets: BTreeSet<&String> = transactions.iter().flatten().map(|(wallet, _)| wallet).collect();
+        for wallet in wallets {
+            let transfers = transactions.iter().flatten().filter(|(w, _)| w == wallet).map(|(_, t)| t);
+            if let Some(opening) = opening_balance(transfers) {
+                openings.insert(wallet.clone(), opening);
+            }
+        }
+
+        if let Some(first) = transactions.first() {
+            for (wallet, opening) in &openings {
+                if *opening < 0 {
+                    warn!(wallet = %wallet, "Balances are inconsistent with the transfers; skipping opening balance");
+                    continue;
+                }
+                entries.push(Entry {
+                    date: first[0].1.timestamp.date_naive(),
+                    narration: "Opening balance".to_string(),
+                    signature: None,
+                    postings: vec![
+                        posting(self.accounts.wallet(wallet), *opening, "USDC"),
+                        posting(OPENING_ACCOUNT.to_string(), -opening, "USDC"),
+                    ],
+                });
+            }
+        }
+
+        for tx in &transactions {
+            let (_, first) = &tx[0];
+            let mut postings = Vec::new();
+            let mut narration = Vec::new();
+
+            for (wallet, transfer) in tx {
+                // Internal transfers are booked once from the sending side
+                if !transfer.moved_funds() || (transfer.is_internal && transfer.is_incoming) {
+                    continue;
+                }
+                let counterparty = if transfer.is_incoming { &transfer.from_address } else { &transfer.to_address };
+                let counterparty_account = if transfer.is_internal {
+                    self.accounts.wallet(counterparty)
+                } else {
+                    self.accounts.counterparty(counterparty, transfer.is_incoming)
+                };
+
+                postings.push(posting(self.accounts.wallet(wallet), signed_amount(transfer), "USDC"));
+                postings.push(Posting {
+                    counterparty: Some(counterparty.clone()),
+                    ..posting(counterparty_account, -signed_amount(transfer), "USDC")
+                });
+                narration.push(memo(transfer));
+            }
+
+            let fee_payer = tx.iter().find(|(wallet, t)| t.fee_payer.as_ref() == Some(wallet));
+            if let Some((wallet, transfer)) = fee_payer {
+                let fee = transfer.fee_lamports.unwrap_or(0) as i128;
+                postings.push(posting(FEES_ACCOUNT.to_string(), fee, "SOL"));
+                postings.push(posting(self.accounts.wallet(wallet), -fee, "SOL"));
+                if narration.is_empty() {
+                    narration.push("Failed transaction".to_string());
+                }
+            }
+
+            if postings.is_empty() {
+                continue;
+            }
+
+            for (wallet, transfer) in tx {
+                let Some(balance) = transfer.balance_after else { continue };
+                if !openings.get(wallet).is_some_and(|opening| *opening >= 0) {
+                    continue;
+                }
+                let account = self.accounts.wallet(wallet);
+                if let Some(last) = postings.iter_mut().rev().find(|p| p.account == account && p.currency == "USDC") {
+                    last.assertion = Some(balance);
+                }
+            }
+
+            entries.push(Entry {
+                date: first.timestamp.date_naive(),
+                narration: narration.join("; "),
+                signature: Some(first.signature.clone()),
+                postings,
+            });
+        }
+
+        entries
+    }
+
+    fn write_beancount(&mut self, entries: &[Entry]) -> Result<()> {
+        let Some(first) = entries.first() else { return Ok(()) };
+
+        let accounts: BTreeSet<&str> = entries.iter().flat_map(|e| &e.postings).map(|p| p.account.as_str()).collect();
+        for account in accounts {
+            writeln!(self.writer, "{} open {}", first.date, account)?;
+        }
+
+        // Beancount checks balances at the start of a day, so the balance
+        // after the last transaction of a day is asserted on the next one
+        let mut balances: BTreeMap<&str, u64> = BTreeMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            writeln!(self.writer)?;
+            writeln!(self.writer, "{} * {}", entry.date, quote(&entry.narration))?;
+            if let Some(signature) = &entry.signature {
+                writeln!(self.writer, "  signature: {}", quote(signature))?;
+            }
+            for p in &entry.postings {
+                writeln!(self.writer, "  {}  {} {}", p.account, amount(p), p.currency)?;
+                if let Some(counterparty) = &p.counterparty {
+                    writeln!(self.writer, "    counterparty: {}", quote(counterparty))?;
+                }
+                if let Some(balance) = p.assertion {
+                    balances.insert(&p.account, balance);
+                }
+            }
+
+            let day_ends = entries.get(i + 1).is_none_or(|next| next.date != entry.date);
+            if day_ends && !balances.is_empty() {
+                writeln!(self.writer)?;
+                for (account, balance) in std::mem::take(&mut balances) {
+                    let date = entry.date + Duration::days(1);
+                    writeln!(self.writer, "{} balance {}  {} USDC", date, account, format_units(balance, USDC_DECIMALS))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_ledger(&mut self, entries: &[Entry]) -> Result<()> {
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(self.writer)?;
+            }
+            writeln!(self.writer, "{} * {}", entry.date.format("%Y/%m/%d"), entry.narration)?;
+            if let Some(signature) = &entry.signature {
+                writeln!(self.writer, "    ; signature: {}", signature)?;
+            }
+            for p in &entry.postings {
+                write!(self.writer, "    {}  {} {}", p.account, amount(p), p.currency)?;
+                if let Some(balance) = p.assertion {
+                    write!(self.writer, " = {} USDC", format_units(balance, USDC_DECIMALS))?;
+                }
+                if let Some(counterparty) = &p.counterparty {
+                    write!(self.writer, "  ; counterparty: {}", counterparty)?;
+                }
+                writeln!(self.writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferSink for LedgerSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.legs.extend(transfers.iter().map(|t| (wallet.to_string(), t.clone())));
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let entries = self.entries();
+        match self.dialect {
+            Dialect::Beancount => self.write_beancount(&entries)?,
+            Dialect::Ledger => self.write_ledger(&entries)?,
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Beancount string literal; labels and memos may contain quotes or backslashes
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn posting(account: String, amount: i128, currency: &'static str) -> Posting {
+    Posting {
+        account,
+        amount,
+        currency,
+        counterparty: None,
+        assertion: None,
+    }
+}
+
+fn amount(posting: &Posting) -> String {
+    let decimals = if posting.currency == "SOL" { SOL_DECIMALS } else { USDC_DECIMALS };
+    let sign = if posting.amount < 0 { "-" } else { "" };
+    format!("{}{}", sign, format_units(posting.amount.unsigned_abs() as u64, decimals))
+}
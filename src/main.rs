@@ -1,70 +1,781 @@
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Duration, Utc};
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use solana_client::{
-    rpc_client::RpcClient,
-    rpc_config::{RpcTransactionConfig, RpcAccountInfoConfig},
-    rpc_request::RpcRequest,
+use chrono_tz::Tz;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
 };
-use solana_sdk::{
-    commitment_config::CommitmentConfig,
-    pubkey::Pubkey,
-    signature::Signature,
-};
-use spl_token::instruction::TokenInstruction;
-use std::{collections::HashSet, str::FromStr};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
 
 mod types;
 mod parser;
 mod indexer;
+mod group;
+mod instructions;
+mod delegation;
+mod errors;
+mod trace;
+mod sqlite;
+mod postgres;
+mod sink;
+mod checkpoint;
+mod backfill;
+mod category;
+mod parquet_export;
+mod accounting;
+mod ledger;
+mod balance;
+mod camt;
+mod ofx;
+mod xml;
+mod verify;
+mod report;
+mod counterparty;
+mod addressbook;
+mod server;
+mod graphql;
 
 use types::*;
 use indexer::SolanaIndexer;
+use group::{GroupReport, WalletGroup};
+use sqlite::SqliteStore;
+use postgres::PostgresStore;
+use sink::{CsvSink, FanOut, NdjsonSink, TransferSink};
+use checkpoint::CheckpointStore;
+use parquet_export::ParquetSink;
+use accounting::{AccountingPreset, AccountingSink};
+use ledger::{AccountMap, Dialect, LedgerSink};
+use camt::CamtSink;
+use balance::{BalanceAt, BalancePoint};
+use ofx::{StatementFormat, StatementSink};
+use backfill::{Backfill, BackfillSummary};
+use verify::Verification;
+use report::{Period, Report};
+use counterparty::Rank;
+use addressbook::{display, AddressBook, AddressEntry, EntityType};
+use server::AppState;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Wallet address to index
-    #[arg(short, long, default_value = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU")]
+    #[arg(short, long, default_value = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU", global = true)]
     wallet: String,
 
+    /// Other wallets owned by the same entity (comma-separated)
+    #[arg(short, long, value_delimiter = ',', global = true)]
+    group: Vec<String>,
+
     /// Hours to backfill (default: 24)
-    #[arg(short, long, default_value = "24")]
+    #[arg(long, default_value = "24", global = true)]
     hours: u64,
 
-    /// Output format (json or pretty)
-    #[arg(short, long, default_value = "pretty")]
-    output: String,
+    /// Include transfer attempts from failed transactions
+    #[arg(long, global = true)]
+    include_failed: bool,
+
+    /// Include the program invocation tree of every transaction (JSON output)
+    #[arg(long, global = true)]
+    trace: bool,
+
+    /// Skip looking up each transaction's position in its block (one getBlock
+    /// call per slot); transactions in the same slot are then ordered by
+    /// signature
+    #[arg(long, global = true)]
+    no_block_positions: bool,
+
+    /// SQLite database to store indexed transfers in
+    #[arg(long, env = "INDEXER_DB", global = true)]
+    db: Option<PathBuf>,
+
+    /// Read transfers from the database instead of scanning the chain
+    #[arg(long, requires = "db", global = true)]
+    from_db: bool,
+
+    /// PostgreSQL connection URL to store indexed transfers in
+    #[arg(long, env = "DATABASE_URL", global = true)]
+    postgres_url: Option<String>,
+
+    /// Only index signatures newer than each wallet's checkpoint, kept in the
+    /// --postgres-url or --db database
+    #[arg(long, conflicts_with = "from_db", global = true)]
+    incremental: bool,
+
+    /// Run --hours as a resumable backfill job recorded in the --db database;
+    /// rerunning the same command resumes it
+    #[arg(long, requires = "db", conflicts_with_all = ["from_db", "incremental"])]
+    backfill: bool,
+
+    /// Size of each backfill chunk in hours
+    #[arg(long, default_value = "24", global = true)]
+    chunk_hours: u64,
+
+    /// Print the USDC balance of each wallet at this time (RFC 3339) instead
+    /// of the report; the time must fall within --hours
+    #[arg(long, conflicts_with_all = ["incremental", "backfill"])]
+    balance_at: Option<DateTime<Utc>>,
+
+    /// Print the USDC balance of each wallet at this slot instead of the report
+    #[arg(long, conflicts_with_all = ["balance_at", "incremental", "backfill"])]
+    balance_at_slot: Option<u64>,
+
+    /// Extra destinations for transfers as they are indexed (repeatable):
+    /// json (stdout), json:<path>, parquet:<dir>, sqlite:<path> or postgres:<url>
+    #[arg(long, global = true)]
+    sink: Vec<String>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value = "pretty", global = true)]
+    output: OutputFormat,
+
+    /// Column layout of accounting output
+    #[arg(long, value_enum, default_value = "double-entry", global = true)]
+    preset: AccountingPreset,
+
+    /// Address book file (JSON, or CSV by extension) labelling counterparties
+    /// in all output, on top of well-known programs and exchanges
+    #[arg(long, env = "INDEXER_ADDRESS_BOOK", global = true)]
+    address_book: Option<PathBuf>,
+
+    /// CSV file of `address,account` rows naming the accounts of wallets and
+    /// counterparties in beancount and ledger output
+    #[arg(long, global = true)]
+    accounts: Option<PathBuf>,
+
+    /// Write output to this file instead of stdout; for parquet, the directory
+    /// to write partitions to
+    #[arg(long, required_if_eq("output", "parquet"), global = true)]
+    out: Option<PathBuf>,
+
+    /// Only log errors (RUST_LOG takes precedence)
+    #[arg(short, long, conflicts_with = "verbose", global = true)]
+    quiet: bool,
+
+    /// Log more detail; repeat for trace logs (RUST_LOG takes precedence)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Log format on stderr
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    log_format: LogFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the indexed closing balance of each wallet against its USDC token
+    /// accounts on chain, and find the transactions that explain a difference
+    Verify,
+    /// Roll transfers up into daily, weekly or monthly periods with totals per
+    /// category; --output picks a table (pretty), json or csv
+    Report {
+        /// Length of each period
+        #[arg(long, value_enum, default_value = "daily")]
+        period: Period,
+
+        /// Time zone the periods start and end in, e.g. Europe/Berlin
+        #[arg(long, default_value = "UTC")]
+        tz: Tz,
+    },
+    /// Totals, counts and first/last seen per counterparty, ranked; --output
+    /// picks pretty or json
+    Counterparties {
+        /// Number of counterparties to show (0 for all)
+        #[arg(long, default_value = "10")]
+        top: usize,
+
+        /// What to rank counterparties by
+        #[arg(long, value_enum, default_value = "volume")]
+        by: Rank,
+    },
+    /// Serve the HTTP API, answering from --db when given and from the chain
+    /// otherwise
+    Serve {
+        /// Port to listen on
+        #[arg(long, env = "PORT", default_value = "8080")]
+        port: u16,
+
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0")]
+        host: IpAddr,
+
+        /// Seconds between checks of --db for newly indexed transfers to send
+        /// to GraphQL subscribers
+        #[arg(long, default_value = "5")]
+        poll_secs: u64,
+    },
+    /// List, edit, import or export the address book in --address-book
+    AddressBook {
+        #[command(subcommand)]
+        action: AddressBookCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AddressBookCommand {
+    /// Print the entries
+    List {
+        /// Include the well-known entries shipped with the indexer
+        #[arg(long)]
+        all: bool,
+    },
+    /// Add or replace an entry
+    Add {
+        address: String,
+        label: String,
+        #[arg(long, value_enum, default_value = "other")]
+        entity_type: EntityType,
+    },
+    /// Remove an entry
+    Remove { address: String },
+    /// Add or replace entries from a JSON or CSV file
+    Import { file: PathBuf },
+    /// Write the entries to a JSON or CSV file
+    Export {
+        file: PathBuf,
+        /// Include the well-known entries shipped with the indexer
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable summary
+    Pretty,
+    /// Full report as one JSON document once indexing is done
+    Json,
+    /// One JSON transfer per line, streamed as transactions are parsed
+    Ndjson,
+    /// Transfers as CSV rows with a fixed header, streamed
+    Csv,
+    /// No output, only write to --postgres-url
+    Postgres,
+    /// Parquet files in --out, partitioned by date
+    Parquet,
+    /// CSV for accounting and tax tools in the layout of --preset, with fee rows
+    Accounting,
+    /// Beancount journal with fees, signatures and balance assertions
+    Beancount,
+    /// Ledger-cli journal with fees, signatures and balance assertions
+    Ledger,
+    /// ISO 20022 camt.053 XML with a statement per wallet and day
+    Camt053,
+    /// OFX bank statement per wallet for personal finance tools
+    Ofx,
+    /// QIF bank transactions per wallet
+    Qif,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
     
-    println!("🚀 Starting Solana USDC Indexer");
-    println!("📍 Wallet: {}", args.wallet);
-    println!("⏰ Backfilling last {} hours", args.hours);
+    init_logging(&args)?;
+
+    let mut address_book = match &args.address_book {
+        Some(path) => AddressBook::open(path)?,
+        None => AddressBook::default(),
+    };
+    if let Some(Command::AddressBook { action }) = &args.command {
+        return manage_address_book(&args, &mut address_book, action);
+    }
+    if let Some(Command::Serve { port, host, poll_secs }) = &args.command {
+        // Positions cost a getBlock per slot inside each request
+        let indexer = SolanaIndexer::new()?
+            .with_failed_transactions(args.include_failed)
+            .with_traces(args.trace)
+            .with_block_positions(false)
+            .with_address_book(address_book.clone());
+        let store = args.db.as_ref().map(SqliteStore::open).transpose()?;
+        let state = AppState::new(indexer, store, address_book, args.hours);
+        let poll_interval = std::time::Duration::from_secs((*poll_secs).max(1));
+        return server::serve(SocketAddr::new(*host, *port), state, poll_interval).await;
+    }
+    info!(wallet = %args.wallet, hours = args.hours, "Starting Solana USDC Indexer");
     
     let wallet_pubkey = Pubkey::from_str(&args.wallet)
         .map_err(|e| anyhow!("Invalid wallet address: {}", e))?;
+
+    let mut wallets = vec![wallet_pubkey];
+    for address in &args.group {
+        let pubkey = Pubkey::from_str(address)
+            .map_err(|e| anyhow!("Invalid group wallet address {}: {}", address, e))?;
+        if !wallets.contains(&pubkey) {
+            wallets.push(pubkey);
+        }
+    }
+
+    if args.command.is_some() && (args.backfill || balance_point(&args).is_some()) {
+        return Err(anyhow!("--backfill, --balance-at and --balance-at-slot do not apply to subcommands"));
+    }
+    let reporting = matches!(args.command, Some(Command::Report { .. } | Command::Counterparties { .. }));
+    match (&args.command, args.output) {
+        (Some(Command::Report { .. }), OutputFormat::Pretty | OutputFormat::Json | OutputFormat::Csv) => {}
+        (Some(Command::Report { .. }), _) => return Err(anyhow!("report supports pretty, json and csv output")),
+        (Some(Command::Counterparties { .. }), OutputFormat::Pretty | OutputFormat::Json) => {}
+        (Some(Command::Counterparties { .. }), _) => {
+            return Err(anyhow!("counterparties supports pretty and json output"));
+        }
+        _ => {}
+    }
+
+    let group = WalletGroup::new(&wallets);
+    let indexer = SolanaIndexer::new()?
+        .with_failed_transactions(args.include_failed)
+        .with_traces(args.trace)
+        .with_block_positions(!args.no_block_positions)
+        .with_group(group.clone())
+        .with_address_book(address_book.clone());
+
+    // With --from-db the SQLite database is read from instead of written to
+    let mut reader = None;
+    let mut sinks = FanOut::default();
+    if let Some(path) = &args.db {
+        let store = SqliteStore::open(path)?;
+        if args.from_db {
+            reader = Some(store);
+        } else {
+            sinks.push(Box::new(store));
+        }
+    }
+    match &args.postgres_url {
+        Some(url) => sinks.push(Box::new(PostgresStore::connect(url).await?)),
+        None if args.output == OutputFormat::Postgres => {
+            return Err(anyhow!("--output=postgres requires --postgres-url or DATABASE_URL"));
+        }
+        None => {}
+    }
+    for spec in &args.sink {
+        sinks.push(sink::open(spec).await?);
+    }
+    match args.output {
+        // Reports are written once all wallets are loaded
+        _ if reporting => {}
+        OutputFormat::Ndjson => sinks.push(Box::new(NdjsonSink::new(sink::open_writer(args.out.as_ref())?))),
+        OutputFormat::Csv => sinks.push(Box::new(CsvSink::new(sink::open_writer(args.out.as_ref())?))),
+        OutputFormat::Accounting => {
+            sinks.push(Box::new(AccountingSink::new(sink::open_writer(args.out.as_ref())?, args.preset)));
+        }
+        OutputFormat::Beancount | OutputFormat::Ledger => {
+            let dialect = if args.output == OutputFormat::Beancount { Dialect::Beancount } else { Dialect::Ledger };
+            let accounts = args.accounts.as_ref().map(AccountMap::load).transpose()?.unwrap_or_default();
+            sinks.push(Box::new(LedgerSink::new(sink::open_writer(args.out.as_ref())?, dialect, accounts)));
+        }
+        OutputFormat::Camt053 => sinks.push(Box::new(CamtSink::new(sink::open_writer(args.out.as_ref())?))),
+        OutputFormat::Ofx | OutputFormat::Qif => {
+            let format = if args.output == OutputFormat::Ofx { StatementFormat::Ofx } else { StatementFormat::Qif };
+            sinks.push(Box::new(StatementSink::new(sink::open_writer(args.out.as_ref())?, format)));
+        }
+        OutputFormat::Parquet => {
+            let dir = args.out.as_ref().ok_or_else(|| anyhow!("--output=parquet requires --out <dir>"))?;
+            sinks.push(Box::new(ParquetSink::new(dir)));
+        }
+        OutputFormat::Pretty | OutputFormat::Postgres if args.out.is_some() => {
+            return Err(anyhow!("--out does not apply to pretty or postgres output"));
+        }
+        _ => {}
+    }
+
+    // Checkpoints get their own connection since the stores above belong to the sinks
+    let mut checkpoints: Option<Box<dyn CheckpointStore>> = match (&args.postgres_url, &args.db) {
+        _ if !args.incremental => None,
+        (Some(url), _) => Some(Box::new(PostgresStore::connect(url).await?)),
+        (None, Some(path)) => Some(Box::new(SqliteStore::open(path)?)),
+        (None, None) => return Err(anyhow!("--incremental requires --db or --postgres-url")),
+    };
+
+    if args.backfill {
+        let path = args.db.as_ref().ok_or_else(|| anyhow!("--backfill requires --db"))?;
+        let mut jobs = SqliteStore::open(path)?;
+        let mut failed = 0;
+
+        for wallet in &wallets {
+            let summary = Backfill::new(&indexer, &mut jobs, args.hours, args.chunk_hours)
+                .run(*wallet, &mut sinks)
+                .await?;
+            // Keep stdout clean for machine-readable formats
+            if matches!(args.output, OutputFormat::Pretty) {
+                print_backfill_summary(&summary);
+            } else {
+                log_backfill_summary(&summary);
+            }
+            failed += summary.failed.len();
+        }
+        sinks.close().await?;
+
+        if failed > 0 {
+            return Err(anyhow!("{} backfill chunks failed; rerun the same command to retry them", failed));
+        }
+        return Ok(());
+    }
+
+    // Skip the report when only writing to databases or streaming transfers
+    let print_report = (reporting || matches!(args.output, OutputFormat::Pretty | OutputFormat::Json))
+        && !args.sink.iter().any(|spec| sink::writes_stdout(spec));
+
+    if wallets.len() > 1 {
+        info!(wallets = wallets.len(), "Indexing wallet group");
+
+        let mut per_wallet = BTreeMap::new();
+        for wallet in &wallets {
+            info!(wallet = %wallet, "Indexing group wallet");
+            let activity = load_activity(&args, &indexer, reader.as_ref(), &mut checkpoints, &mut sinks, &address_book, wallet).await?;
+            per_wallet.insert(wallet.to_string(), activity);
+        }
+
+        sinks.close().await?;
+        for (wallet, activity) in &per_wallet {
+            advance_checkpoint(&mut checkpoints, wallet, activity).await?;
+        }
+
+        if matches!(args.command, Some(Command::Verify)) {
+            let activities: Vec<_> = wallets.iter().map(|w| (w, &per_wallet[&w.to_string()])).collect();
+            return verify_wallets(&args, &indexer, &activities, print_report).await;
+        }
+        if let Some(Command::Report { period, tz }) = &args.command {
+            let report = report::build(per_wallet.values().flat_map(|a| &a.transfers), *period, *tz);
+            return if print_report { write_report(&args, &report) } else { Ok(()) };
+        }
+        if let Some(Command::Counterparties { top, by }) = &args.command {
+            let transfers = per_wallet.values().flat_map(|a| &a.transfers);
+            return print_counterparties(&args, transfers, *top, *by, &address_book, print_report);
+        }
+        if let Some(point) = balance_point(&args) {
+            let balances = per_wallet
+                .iter()
+                .map(|(wallet, activity)| balance::balance_at(wallet, activity, point, indexed_since(&args)))
+                .collect::<Result<Vec<_>>>()?;
+            if print_report {
+                print_balances(&args, &balances)?;
+            }
+            return Ok(());
+        }
+        let report = group.build_report(per_wallet);
+
+        match args.output {
+            _ if !print_report => {}
+            OutputFormat::Json => {
+                write_json(args.out.as_ref(), &report)?;
+            }
+            _ => {
+                print_group_pretty(&report);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let activity = load_activity(&args, &indexer, reader.as_ref(), &mut checkpoints, &mut sinks, &address_book, &wallet_pubkey).await?;
+    sinks.close().await?;
+    advance_checkpoint(&mut checkpoints, &args.wallet, &activity).await?;
+
+    if matches!(args.command, Some(Command::Verify)) {
+        return verify_wallets(&args, &indexer, &[(&wallet_pubkey, &activity)], print_report).await;
+    }
+    if let Some(Command::Report { period, tz }) = &args.command {
+        let report = report::build(&activity.transfers, *period, *tz);
+        return if print_report { write_report(&args, &report) } else { Ok(()) };
+    }
+    if let Some(Command::Counterparties { top, by }) = &args.command {
+        return print_counterparties(&args, &activity.transfers, *top, *by, &address_book, print_report);
+    }
+    if let Some(point) = balance_point(&args) {
+        let balance = balance::balance_at(&args.wallet, &activity, point, indexed_since(&args))?;
+        if print_report {
+            print_balances(&args, &[balance])?;
+        }
+        return Ok(());
+    }
     
-    let indexer = SolanaIndexer::new()?;
-    let transfers = indexer.get_usdc_transfers(wallet_pubkey, args.hours).await?;
-    
-    match args.output.as_str() {
-        "json" => {
-            println!("{}", serde_json::to_string_pretty(&transfers)?);
+    match args.output {
+        _ if !print_report => {}
+        OutputFormat::Json => {
+            write_json(args.out.as_ref(), &activity)?;
         }
         _ => {
-            print_transfers_pretty(&transfers);
+            print_transfers_pretty(&activity);
+            print_delegations_pretty(&activity);
+            print_lifecycle_pretty(&activity.lifecycle);
         }
     }
     
     Ok(())
 }
 
-fn print_transfers_pretty(transfers: &[UsdcTransfer]) {
+// Diagnostics go to stderr so stdout only carries data
+fn init_logging(args: &Args) -> Result<()> {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => "error",
+        (false, 0) => "info",
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,indexer={}", level)));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr);
+
+    match args.log_format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+    .map_err(|e| anyhow!("Failed to initialize logging: {}", e))
+}
+
+// Index a wallet, handing its transfers to the sinks, or read it back from
+// the SQLite database with --from-db
+async fn load_activity(
+    args: &Args,
+    indexer: &SolanaIndexer,
+    reader: Option<&SqliteStore>,
+    checkpoints: &mut Option<Box<dyn CheckpointStore>>,
+    sinks: &mut FanOut,
+    address_book: &AddressBook,
+    wallet: &Pubkey,
+) -> Result<WalletActivity> {
+    let wallet_address = wallet.to_string();
+
+    if !args.from_db {
+        let checkpoint = match checkpoints {
+            Some(store) => store.load_checkpoint(&wallet_address).await?,
+            None => None,
+        };
+        return indexer
+            .get_wallet_activity(*wallet, args.hours, checkpoint.as_ref(), sinks)
+            .await;
+    }
+
+    let store = reader.ok_or_else(|| anyhow!("--from-db requires --db"))?;
+    let mut transfers = store.load_transfers(&wallet_address, indexed_since(args))?;
+    address_book.label_transfers(&mut transfers);
+    info!(wallet = %wallet_address, transfers = transfers.len(), "Loaded transfers from database");
+
+    sinks.begin(&wallet_address, args.hours).await?;
+    sinks.write(&wallet_address, &transfers).await?;
+    sinks.finish(&wallet_address, transfers.len()).await?;
+
+    let mut activity = WalletActivity {
+        transfers,
+        ..Default::default()
+    };
+    balance::fill_balances(&mut activity);
+    Ok(activity)
+}
+
+// Start of the range covered by this run
+fn indexed_since(args: &Args) -> DateTime<Utc> {
+    Utc::now() - Duration::hours(args.hours as i64)
+}
+
+fn balance_point(args: &Args) -> Option<BalancePoint> {
+    match (args.balance_at, args.balance_at_slot) {
+        (Some(time), _) => Some(BalancePoint::Time(time)),
+        (None, Some(slot)) => Some(BalancePoint::Slot(slot)),
+        (None, None) => None,
+    }
+}
+
+fn print_balances(args: &Args, balances: &[BalanceAt]) -> Result<()> {
+    if args.output == OutputFormat::Json {
+        return match balances {
+            [balance] => write_json(args.out.as_ref(), balance),
+            _ => write_json(args.out.as_ref(), &balances),
+        };
+    }
+
+    for balance in balances {
+        let source = match (&balance.signature, balance.slot) {
+            (Some(signature), Some(slot)) => format!("after {} in slot {}", signature, slot),
+            _ => "opening balance of the indexed range".to_string(),
+        };
+        println!("💰 {}: ${:.6} USDC ({})", balance.wallet, balance.balance, source);
+    }
+    Ok(())
+}
+
+// Compare each wallet's indexed closing balance with its balance on chain,
+// failing if any of them differ
+async fn verify_wallets(
+    args: &Args,
+    indexer: &SolanaIndexer,
+    activities: &[(&Pubkey, &WalletActivity)],
+    print: bool,
+) -> Result<()> {
+    let mut verifications = Vec::new();
+    for (wallet, activity) in activities {
+        verifications.push(verify::verify_wallet(indexer, wallet, activity).await?);
+    }
+
+    if print {
+        print_verifications(args, &verifications)?;
+    }
+
+    let mismatched = verifications.iter().filter(|v| !v.matches).count();
+    if mismatched > 0 {
+        return Err(anyhow!("{} wallets do not match their on-chain USDC balance", mismatched));
+    }
+    Ok(())
+}
+
+fn print_verifications(args: &Args, verifications: &[Verification]) -> Result<()> {
+    if args.output == OutputFormat::Json {
+        return match verifications {
+            [verification] => write_json(args.out.as_ref(), verification),
+            _ => write_json(args.out.as_ref(), &verifications),
+        };
+    }
+
+    for v in verifications {
+        if v.matches {
+            println!(
+                "✅ {}: indexed ${:.6} USDC matches {} token accounts on chain",
+                v.wallet,
+                v.indexed_balance,
+                v.accounts.len()
+            );
+            continue;
+        }
+
+        println!(
+            "❌ {}: indexed ${:.6} USDC, on chain ${:.6} USDC, difference ${:.6}",
+            v.wallet, v.indexed_balance, v.onchain_balance, v.difference
+        );
+        for account in &v.accounts {
+            println!("   Token account {}: ${:.6}", account.address, account.balance);
+        }
+        for d in &v.discrepancies {
+            println!(
+                "   ⚠️  {} (slot {}, {}): parsed {:+.6}, balance changed {:+.6}, unexplained {:+.6}",
+                d.signature,
+                d.slot,
+                d.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                d.parsed_delta,
+                d.observed_delta,
+                d.unexplained
+            );
+        }
+        if v.unexplained_after_last != 0.0 {
+            println!(
+                "   ⚠️  {:+.6} unexplained after the last indexed transaction (outside --hours or not indexed)",
+                v.unexplained_after_last
+            );
+        }
+    }
+    Ok(())
+}
+
+fn write_report(args: &Args, report: &Report) -> Result<()> {
+    match args.output {
+        OutputFormat::Json => write_json(args.out.as_ref(), report),
+        OutputFormat::Csv => report::write_csv(sink::open_writer(args.out.as_ref())?, report),
+        _ => report::write_table(&mut sink::open_writer(args.out.as_ref())?, report),
+    }
+}
+
+fn print_counterparties<'a>(
+    args: &Args,
+    transfers: impl IntoIterator<Item = &'a UsdcTransfer>,
+    top: usize,
+    by: Rank,
+    address_book: &AddressBook,
+    print: bool,
+) -> Result<()> {
+    let counterparties = counterparty::rank(transfers, by, top, address_book);
+    match args.output {
+        _ if !print => Ok(()),
+        OutputFormat::Json => write_json(args.out.as_ref(), &counterparties),
+        _ => counterparty::write_table(&mut sink::open_writer(args.out.as_ref())?, &counterparties),
+    }
+}
+
+fn manage_address_book(args: &Args, address_book: &mut AddressBook, action: &AddressBookCommand) -> Result<()> {
+    match action {
+        AddressBookCommand::List { all } => {
+            let entries = address_book.entries(*all);
+            if args.output == OutputFormat::Json {
+                return write_json(args.out.as_ref(), &entries);
+            }
+            for entry in entries {
+                println!("{:<44}  {:<9}  {}", entry.address, entry.entity_type.as_str(), entry.label);
+            }
+        }
+        AddressBookCommand::Add { address, label, entity_type } => {
+            Pubkey::from_str(address).map_err(|e| anyhow!("Invalid address {}: {}", address, e))?;
+            address_book.insert(AddressEntry {
+                address: address.clone(),
+                label: label.clone(),
+                entity_type: *entity_type,
+            });
+            address_book.save()?;
+            info!(address = %address, label = %label, "Saved address book entry");
+        }
+        AddressBookCommand::Remove { address } => {
+            if !address_book.remove(address) {
+                return Err(anyhow!("{} is not in the address book", address));
+            }
+            address_book.save()?;
+            info!(address = %address, "Removed address book entry");
+        }
+        AddressBookCommand::Import { file } => {
+            let count = address_book.import(file)?;
+            address_book.save()?;
+            info!(entries = count, file = %file.display(), "Imported address book entries");
+        }
+        AddressBookCommand::Export { file, all } => {
+            let count = address_book.export(file, *all)?;
+            info!(entries = count, file = %file.display(), "Exported address book entries");
+        }
+    }
+    Ok(())
+}
+
+// Move a wallet's checkpoint forward. Only called once the sinks are closed,
+// so a crash before this point re-indexes the range instead of skipping it.
+async fn advance_checkpoint(
+    store: &mut Option<Box<dyn CheckpointStore>>,
+    wallet: &str,
+    activity: &WalletActivity,
+) -> Result<()> {
+    if let (Some(store), Some(checkpoint)) = (store, &activity.checkpoint) {
+        // Moving past skipped transactions would lose their transfers for good
+        if activity.skipped > 0 {
+            warn!(wallet, skipped = activity.skipped, "Not advancing checkpoint; skipped transactions are retried next run");
+            return Ok(());
+        }
+        store.save_checkpoint(wallet, checkpoint).await?;
+        info!(wallet, slot = checkpoint.slot, "Advanced checkpoint");
+    }
+    Ok(())
+}
+
+// Pretty-printed JSON to the --out file, or stdout
+fn write_json(out: Option<&PathBuf>, value: &impl serde::Serialize) -> Result<()> {
+    let mut writer = sink::open_writer(out)?;
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_transfers_pretty(activity: &WalletActivity) {
+    let transfers = &activity.transfers;
     println!("\n📊 USDC Transfer Summary");
     println!("═══════════════════════════════════════════════════════════════");
     println!("Found {} USDC transfers", transfers.len());
@@ -77,9 +788,17 @@ fn print_transfers_pretty(transfers: &[UsdcTransfer]) {
     
     let mut total_sent = 0.0;
     let mut total_received = 0.0;
+    let mut failed = 0;
     
     for transfer in transfers {
-        let direction = if transfer.is_incoming { "📥 RECEIVED" } else { "📤 SENT" };
+        let direction = match (transfer.error.is_some(), transfer.is_internal, transfer.is_incoming) {
+            (true, _, true) => "❌ FAILED RECEIVE",
+            (true, _, false) => "❌ FAILED SEND",
+            (false, true, true) => "🔁 INTERNAL IN",
+            (false, true, false) => "🔁 INTERNAL OUT",
+            (false, false, true) => "📥 RECEIVED",
+            (false, false, false) => "📤 SENT",
+        };
         let amount_formatted = format!("{:.6}", transfer.amount);
         
         println!("🕒 {} | {} | ${} USDC", 
@@ -87,12 +806,35 @@ fn print_transfers_pretty(transfers: &[UsdcTransfer]) {
                  direction,
                  amount_formatted);
         println!("   💳 Transaction: {}", transfer.signature);
-        
-        if transfer.is_incoming {
-            println!("   📨 From: {}", transfer.from_address);
+        let position = transfer.tx_index
+            .map(|i| format!(" #{}", i))
+            .unwrap_or_default();
+        let finality = transfer.finality
+            .map(|f| format!(" ({:?})", f).to_lowercase())
+            .unwrap_or_default();
+        println!("   🧱 Slot: {}{}{}", transfer.slot, position, finality);
+        if !transfer.program_path.is_empty() {
+            println!("   🧭 Via: {}", transfer.program_path.join(" -> "));
+        }
+
+        if let Some(error) = &transfer.error {
+            if transfer.is_incoming {
+                println!("   📨 From: {}", display(&transfer.from_address, transfer.from_label.as_deref()));
+            } else {
+                println!("   📤 To: {}", display(&transfer.to_address, transfer.to_label.as_deref()));
+            }
+            println!("   ⚠️ Reason: {}", error.describe());
+            failed += 1;
+        } else if transfer.is_incoming {
+            println!("   📨 From: {}", display(&transfer.from_address, transfer.from_label.as_deref()));
             total_received += transfer.amount;
         } else {
-            println!("   📤 To: {}", transfer.to_address);
+            println!("   📤 To: {}", display(&transfer.to_address, transfer.to_label.as_deref()));
+            if transfer.authority_kind == Some(AuthorityKind::Delegate) {
+                if let Some(authority) = &transfer.authority {
+                    println!("   🔑 Signed by delegate: {}", authority);
+                }
+            }
             total_sent += transfer.amount;
         }
         println!();
@@ -102,5 +844,157 @@ fn print_transfers_pretty(transfers: &[UsdcTransfer]) {
     println!("💰 Total Received: ${:.6} USDC", total_received);
     println!("💸 Total Sent: ${:.6} USDC", total_sent);
     println!("📈 Net Change: ${:.6} USDC", total_received - total_sent);
+    if let (Some(opening), Some(closing)) = (activity.opening_balance, activity.closing_balance) {
+        println!("🏁 Opening Balance: ${:.6} USDC", opening);
+        println!("🏦 Closing Balance: ${:.6} USDC", closing);
+    }
+    if failed > 0 {
+        println!("❌ Failed Attempts: {}", failed);
+    }
+    println!("═══════════════════════════════════════════════════════════════");
+}
+
+fn print_delegations_pretty(activity: &WalletActivity) {
+    if activity.delegations.is_empty() && activity.outstanding_delegations.is_empty() {
+        return;
+    }
+
+    println!("\n🔑 USDC Delegations");
+    println!("═══════════════════════════════════════════════════════════════");
+
+    for event in &activity.delegations {
+        let kind = match event.kind {
+            DelegationKind::Approve => "✅ APPROVE",
+            DelegationKind::Revoke => "🚫 REVOKE",
+            DelegationKind::DelegatedSpend => "💳 DELEGATED SPEND",
+        };
+        let allowance = event.allowance
+            .map(|a| format!("${:.6}", a))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        println!("🕒 {} | {} | ${:.6} USDC | allowance left: {}",
+                 event.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                 kind,
+                 event.amount,
+                 allowance);
+        println!("   💳 Transaction: {}", event.signature);
+        println!("   🏦 Token account: {}", event.token_account);
+        if let Some(delegate) = &event.delegate {
+            println!("   👤 Delegate: {}", delegate);
+        }
+        println!();
+    }
+
+    if !activity.outstanding_delegations.is_empty() {
+        println!("Outstanding delegations:");
+        for delegation in &activity.outstanding_delegations {
+            println!("   🏦 {} -> 👤 {} | ${:.6} USDC (approved {})",
+                     delegation.token_account,
+                     delegation.delegate,
+                     delegation.allowance,
+                     delegation.approved_at.format("%Y-%m-%d %H:%M:%S UTC"));
+        }
+    }
+    println!("═══════════════════════════════════════════════════════════════");
+}
+
+fn print_lifecycle_pretty(events: &[LifecycleEvent]) {
+    if events.is_empty() {
+        return;
+    }
+
+    println!("\n🏦 USDC Account Lifecycle");
     println!("═══════════════════════════════════════════════════════════════");
-}
\ No newline at end of file
+
+    for event in events {
+        let kind = match event.kind {
+            LifecycleKind::Initialize => "🆕 INITIALIZED",
+            LifecycleKind::CreateAssociated => "🆕 ATA CREATED",
+            LifecycleKind::Close => "🗑️ CLOSED",
+            LifecycleKind::Freeze => "🧊 FROZEN",
+            LifecycleKind::Thaw => "🔥 THAWED",
+            LifecycleKind::OwnerChange => "🔀 OWNER CHANGED",
+        };
+
+        println!("🕒 {} | {} | {}",
+                 event.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                 kind,
+                 event.token_account);
+        println!("   💳 Transaction: {}", event.signature);
+
+        if let Some(lamports) = event.rent_lamports {
+            let sol = lamports as f64 / 1_000_000_000.0;
+            if let Some(payer) = &event.rent_payer {
+                println!("   💸 Rent paid: {:.9} SOL by {}", sol, payer);
+            } else if let Some(recipient) = &event.rent_recipient {
+                println!("   💰 Rent reclaimed: {:.9} SOL to {}", sol, recipient);
+            }
+        }
+        if let Some(authority) = &event.authority {
+            println!("   🔑 Authority: {}", authority);
+        }
+        if let Some(new_owner) = &event.new_owner {
+            println!("   👤 New owner: {}", new_owner);
+        }
+        println!();
+    }
+    println!("═══════════════════════════════════════════════════════════════");
+}
+
+fn print_backfill_summary(summary: &BackfillSummary) {
+    println!("\n📦 Backfill Job {} ({})", summary.job.id, summary.job.wallet);
+    println!("═══════════════════════════════════════════════════════════════");
+    println!("🗓️ Range: {} to {}",
+             summary.job.start.format("%Y-%m-%d %H:%M:%S UTC"),
+             summary.job.end.format("%Y-%m-%d %H:%M:%S UTC"));
+    println!("✅ Chunks done: {}/{}", summary.done, summary.chunks);
+    println!("💳 Transfers found: {}", summary.transfers_found);
+
+    for chunk in &summary.failed {
+        println!("❌ Chunk {} ({} to {}) failed after {} attempts: {}",
+                 chunk.index + 1,
+                 chunk.start.format("%Y-%m-%d %H:%M"),
+                 chunk.end.format("%Y-%m-%d %H:%M"),
+                 chunk.attempts,
+                 chunk.last_error.as_deref().unwrap_or("unknown error"));
+    }
+    println!("═══════════════════════════════════════════════════════════════");
+}
+
+fn log_backfill_summary(summary: &BackfillSummary) {
+    info!(
+        job = summary.job.id,
+        wallet = %summary.job.wallet,
+        done = summary.done,
+        chunks = summary.chunks,
+        transfers = summary.transfers_found,
+        "Backfill finished"
+    );
+    for chunk in &summary.failed {
+        warn!(
+            job = summary.job.id,
+            chunk = chunk.index + 1,
+            attempts = chunk.attempts,
+            error = chunk.last_error.as_deref().unwrap_or("unknown error"),
+            "Backfill chunk failed"
+        );
+    }
+}
+
+fn print_group_pretty(report: &GroupReport) {
+    for wallet in &report.wallets {
+        println!("\n👛 Wallet: {}", wallet.wallet);
+        print_transfers_pretty(&wallet.activity);
+        print_delegations_pretty(&wallet.activity);
+        print_lifecycle_pretty(&wallet.activity.lifecycle);
+    }
+
+    let summary = &report.summary;
+    println!("\n👥 Group Summary ({} wallets)", summary.wallet_count);
+    println!("═══════════════════════════════════════════════════════════════");
+    println!("🔁 Internal Transfers: {} (${:.6} USDC)", summary.internal_count, summary.internal_volume);
+    println!("💰 External Received: ${:.6} USDC", summary.external_received);
+    println!("💸 External Sent: ${:.6} USDC", summary.external_sent);
+    println!("📈 Net Change: ${:.6} USDC", summary.net_change);
+    println!("═══════════════════════════════════════════════════════════════");
+}
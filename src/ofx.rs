@@ -0,0 +1,188 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, io::Write};
+use tracing::warn;
+
+use crate::accounting::{format_units, memo};
+use crate::addressbook::counterparty;
+use crate::balance::{chain_order, opening_balance, signed_amount};
+use crate::sink::TransferSink;
+use crate::types::*;
+use crate::xml::escape_xml;
+
+// OFX payee names are limited to 32 characters; the memo keeps the full address
+const MAX_PAYEE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatementFormat {
+    Ofx,
+    Qif,
+}
+
+// Writes a bank statement per wallet for personal finance tools once all
+// wallets are done, as OFX 2 with a ledger balance or as QIF
+pub struct StatementSink {
+    writer: Box<dyn Write + Send>,
+    format: StatementFormat,
+    wallets: BTreeMap<String, Vec<UsdcTransfer>>,
+}
+
+impl StatementSink {
+    pub fn new(writer: Box<dyn Write + Send>, format: StatementFormat) -> Self {
+        Self {
+            writer,
+            format,
+            wallets: BTreeMap::new(),
+        }
+    }
+
+    fn write_ofx(&mut self, wallets: &BTreeMap<String, Vec<UsdcTransfer>>) -> Result<()> {
+        let now = ofx_time(&Utc::now());
+        let w = &mut self.writer;
+
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>")?;
+        writeln!(w, "<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>")?;
+        writeln!(w, "<OFX>")?;
+        writeln!(w, "  <SIGNONMSGSRSV1>")?;
+        writeln!(w, "    <SONRS>")?;
+        writeln!(w, "      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>")?;
+        writeln!(w, "      <DTSERVER>{}</DTSERVER>", now)?;
+        writeln!(w, "      <LANGUAGE>ENG</LANGUAGE>")?;
+        writeln!(w, "    </SONRS>")?;
+        writeln!(w, "  </SIGNONMSGSRSV1>")?;
+        writeln!(w, "  <BANKMSGSRSV1>")?;
+
+        for (i, (wallet, transfers)) in wallets.iter().enumerate() {
+            let closing = opening_balance(transfers).unwrap_or_else(|| {
+                warn!(wallet = %wallet, "No balances recorded for the wallet; ledger balance starts from zero");
+                0
+            }) + transfers.iter().map(signed_amount).sum::<i128>();
+            let (Some(first), Some(last)) = (transfers.first(), transfers.last()) else { continue };
+
+            writeln!(w, "    <STMTTRNRS>")?;
+            writeln!(w, "      <TRNUID>{}</TRNUID>", i + 1)?;
+            writeln!(w, "      <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>")?;
+            writeln!(w, "      <STMTRS>")?;
+            // USDC is reported as USD since OFX only knows ISO 4217 currencies
+            writeln!(w, "        <CURDEF>USD</CURDEF>")?;
+            writeln!(w, "        <BANKACCTFROM>")?;
+            writeln!(w, "          <BANKID>SOLANA</BANKID>")?;
+            writeln!(w, "          <ACCTID>{}</ACCTID>", escape_xml(wallet))?;
+            writeln!(w, "          <ACCTTYPE>CHECKING</ACCTTYPE>")?;
+            writeln!(w, "        </BANKACCTFROM>")?;
+            writeln!(w, "        <BANKTRANLIST>")?;
+            writeln!(w, "          <DTSTART>{}</DTSTART>", ofx_time(&first.timestamp))?;
+            writeln!(w, "          <DTEND>{}</DTEND>", ofx_time(&last.timestamp))?;
+
+            for transfer in transfers {
+                let payee: String = payee(transfer).chars().take(MAX_PAYEE).collect();
+
+                writeln!(w, "          <STMTTRN>")?;
+                writeln!(w, "            <TRNTYPE>{}</TRNTYPE>", if transfer.is_incoming { "CREDIT" } else { "DEBIT" })?;
+                writeln!(w, "            <DTPOSTED>{}</DTPOSTED>", ofx_time(&transfer.timestamp))?;
+                writeln!(w, "            <TRNAMT>{}</TRNAMT>", signed(transfer))?;
+                writeln!(w, "            <FITID>{}</FITID>", fitid(transfer))?;
+                writeln!(w, "            <NAME>{}</NAME>", escape_xml(&payee))?;
+                writeln!(w, "            <MEMO>{}</MEMO>", escape_xml(&memo(transfer)))?;
+                writeln!(w, "          </STMTTRN>")?;
+            }
+
+            writeln!(w, "        </BANKTRANLIST>")?;
+            writeln!(w, "        <LEDGERBAL>")?;
+            writeln!(w, "          <BALAMT>{}</BALAMT>", signed_units(closing))?;
+            writeln!(w, "          <DTASOF>{}</DTASOF>", ofx_time(&last.timestamp))?;
+            writeln!(w, "        </LEDGERBAL>")?;
+            writeln!(w, "      </STMTRS>")?;
+            writeln!(w, "    </STMTTRNRS>")?;
+        }
+
+        writeln!(w, "  </BANKMSGSRSV1>")?;
+        writeln!(w, "</OFX>")?;
+        Ok(())
+    }
+
+    fn write_qif(&mut self, wallets: &BTreeMap<String, Vec<UsdcTransfer>>) -> Result<()> {
+        let w = &mut self.writer;
+
+        for (wallet, transfers) in wallets {
+            writeln!(w, "!Account")?;
+            writeln!(w, "N{}", wallet)?;
+            writeln!(w, "TBank")?;
+            writeln!(w, "^")?;
+            writeln!(w, "!Type:Bank")?;
+
+            for transfer in transfers {
+                writeln!(w, "D{}", transfer.timestamp.format("%m/%d/%Y"))?;
+                writeln!(w, "T{}", signed(transfer))?;
+                writeln!(w, "N{}", fitid(transfer))?;
+                writeln!(w, "P{}", payee(transfer))?;
+                writeln!(w, "M{}", memo(transfer))?;
+                writeln!(w, "^")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferSink for StatementSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.wallets
+            .entry(wallet.to_string())
+            .or_default()
+            .extend(transfers.iter().filter(|t| t.moved_funds()).cloned());
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let mut wallets = std::mem::take(&mut self.wallets);
+        for transfers in wallets.values_mut() {
+            transfers.sort_by(chain_order);
+        }
+
+        match self.format {
+            StatementFormat::Ofx => self.write_ofx(&wallets)?,
+            StatementFormat::Qif => self.write_qif(&wallets)?,
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Unique per statement line, from the same fields that key the stored row:
+// the signature, instruction (with the inner instruction for CPIs), which
+// side of the transfer the wallet is on and the wallet's token account, e.g.
+// "<signature>:3:out:<account>" or "<signature>:3.1:out:<account>"; the
+// account is left out where it is unknown
+fn fitid(transfer: &UsdcTransfer) -> String {
+    let mut instruction = transfer.instruction_index.map(|i| i as i64).unwrap_or(-1).to_string();
+    if let Some(inner) = transfer.inner_index {
+        instruction.push_str(&format!(".{}", inner));
+    }
+    let direction = if transfer.is_incoming { "in" } else { "out" };
+    let mut id = format!("{}:{}:{}", transfer.signature, instruction, direction);
+    if let Some(account) = &transfer.token_account {
+        id.push_str(&format!(":{}", account));
+    }
+    id
+}
+
+// Label of the other side if known, otherwise its address
+fn payee(transfer: &UsdcTransfer) -> &str {
+    let (address, label) = counterparty(transfer);
+    label.unwrap_or(address)
+}
+
+fn signed(transfer: &UsdcTransfer) -> String {
+    signed_units(signed_amount(transfer))
+}
+
+fn signed_units(raw: i128) -> String {
+    let sign = if raw < 0 { "-" } else { "" };
+    format!("{}{}", sign, format_units(raw.unsigned_abs() as u64, USDC_DECIMALS))
+}
+
+fn ofx_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%d%H%M%S.000[0:GMT]").to_string()
+}
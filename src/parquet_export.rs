@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use parquet::{
+    basic::Compression,
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type},
+    file::{
+        metadata::KeyValue,
+        properties::WriterProperties,
+        writer::{SerializedColumnWriter, SerializedFileWriter},
+    },
+    schema::{parser::parse_message_type, types::SchemaDescriptor},
+};
+use std::{collections::BTreeMap, fs, fs::File, path::PathBuf, sync::Arc};
+use tracing::info;
+
+use crate::category::categorize;
+use crate::sink::TransferSink;
+use crate::sqlite::to_text;
+use crate::types::*;
+
+// Version of the schema below, stored as "schema_version" in the metadata of
+// every file. Bump it on any change to the columns.
+const SCHEMA_VERSION: &str = "2";
+
+const SCHEMA: &str = "
+message usdc_transfer {
+    required binary wallet (UTF8);
+    required binary signature (UTF8);
+    required int64 slot (INTEGER(64,false));
+    optional int32 tx_index (INTEGER(32,false));
+    optional int32 instruction_index (INTEGER(32,false));
+    required int64 block_time (TIMESTAMP(MILLIS,true));
+    optional binary finality (UTF8);
+    required binary mint (UTF8);
+    required int64 raw_amount (INTEGER(64,false));
+    required int32 decimals (INTEGER(8,false));
+    required double amount;
+    required binary direction (UTF8);
+    required binary from_address (UTF8);
+    required binary to_address (UTF8);
+    required binary counterparty (UTF8);
+    required boolean is_internal;
+    optional binary authority (UTF8);
+    optional binary authority_kind (UTF8);
+    optional binary error (UTF8);
+    repeated binary program_path (UTF8);
+    repeated binary categories (UTF8);
+    optional binary from_label (UTF8);
+    optional binary to_label (UTF8);
+}
+";
+
+// Writes transfers to Parquet files partitioned by UTC date, as
+// `<dir>/date=YYYY-MM-DD/transfers-<export time>-<pid>.parquet`, once all
+// wallets are done
+pub struct ParquetSink {
+    dir: PathBuf,
+    file_name: String,
+    partitions: BTreeMap<NaiveDate, Vec<(String, UsdcTransfer)>>,
+}
+
+impl ParquetSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            // Unique per export so exports of overlapping ranges never clobber
+            // each other, even when two start within the same second
+            file_name: format!(
+                "transfers-{}-{}.parquet",
+                Utc::now().format("%Y%m%dT%H%M%S"),
+                std::process::id()
+            ),
+            partitions: BTreeMap::new(),
+        }
+    }
+
+    fn write_partition(&self, date: NaiveDate, rows: &[(String, UsdcTransfer)]) -> Result<PathBuf> {
+        let partition = self.dir.join(format!("date={}", date.format("%Y-%m-%d")));
+        fs::create_dir_all(&partition)
+            .map_err(|e| anyhow!("Failed to create {}: {}", partition.display(), e))?;
+        let path = partition.join(&self.file_name);
+
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let descriptor = SchemaDescriptor::new(schema.clone());
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                "schema_version".to_string(),
+                SCHEMA_VERSION.to_string(),
+            )]))
+            .build();
+
+        // Refuse to overwrite rather than silently replace an earlier export
+        let file = File::create_new(&path)
+            .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+        let mut writer = SerializedFileWriter::new(file, schema, Arc::new(properties))?;
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+
+        while let Some(mut column) = row_group.next_column()? {
+            write_column(descriptor.column(index).name(), &mut column, rows)?;
+            column.close()?;
+            index += 1;
+        }
+
+        row_group.close()?;
+        writer.close()?;
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl TransferSink for ParquetSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        for transfer in transfers {
+            self.partitions
+                .entry(transfer.timestamp.date_naive())
+                .or_default()
+                .push((wallet.to_string(), transfer.clone()));
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        for (date, rows) in &self.partitions {
+            let path = self.write_partition(*date, rows)?;
+            info!(path = %path.display(), transfers = rows.len(), "Wrote Parquet partition");
+        }
+        Ok(())
+    }
+}
+
+fn write_column(name: &str, column: &mut SerializedColumnWriter, rows: &[(String, UsdcTransfer)]) -> Result<()> {
+    let transfers = rows.iter().map(|(_, t)| t);
+
+    match name {
+        "wallet" => required_strings(column, rows.iter().map(|(wallet, _)| wallet.clone())),
+        "signature" => required_strings(column, transfers.map(|t| t.signature.clone())),
+        "slot" => int64s(column, transfers.map(|t| t.slot as i64)),
+        "tx_index" => optional_int32s(column, transfers.map(|t| t.tx_index.map(|i| i as i32))),
+        "instruction_index" => optional_int32s(column, transfers.map(|t| t.instruction_index.map(|i| i as i32))),
+        "block_time" => int64s(column, transfers.map(|t| t.timestamp.timestamp_millis())),
+        "finality" => optional_strings(column, transfers.map(|t| to_text(t.finality))),
+        "mint" => required_strings(column, transfers.map(|_| USDC_MINT.to_string())),
+        "raw_amount" => int64s(column, transfers.map(|t| t.raw_amount as i64)),
+        "decimals" => {
+            let values: Vec<i32> = transfers.map(|t| t.decimals as i32).collect();
+            column.typed::<Int32Type>().write_batch(&values, None, None)?;
+            Ok(())
+        }
+        "amount" => {
+            let values: Vec<f64> = transfers.map(|t| t.amount).collect();
+            column.typed::<DoubleType>().write_batch(&values, None, None)?;
+            Ok(())
+        }
+        "direction" => required_strings(
+            column,
+            transfers.map(|t| if t.is_incoming { "in" } else { "out" }.to_string()),
+        ),
+        "from_address" => required_strings(column, transfers.map(|t| t.from_address.clone())),
+        "to_address" => required_strings(column, transfers.map(|t| t.to_address.clone())),
+        "counterparty" => required_strings(
+            column,
+            transfers.map(|t| if t.is_incoming { t.from_address.clone() } else { t.to_address.clone() }),
+        ),
+        "is_internal" => {
+            let values: Vec<bool> = transfers.map(|t| t.is_internal).collect();
+            column.typed::<BoolType>().write_batch(&values, None, None)?;
+            Ok(())
+        }
+        "authority" => optional_strings(column, transfers.map(|t| t.authority.clone())),
+        "authority_kind" => optional_strings(column, transfers.map(|t| to_text(t.authority_kind))),
+        "error" => optional_strings(column, transfers.map(|t| t.error.as_ref().map(|e| e.describe()))),
+        "program_path" => repeated_strings(column, transfers.map(|t| t.program_path.clone())),
+        "categories" => repeated_strings(
+            column,
+            transfers.map(|t| categorize(t).iter().map(|c| c.as_str().to_string()).collect()),
+        ),
+        "from_label" => optional_strings(column, transfers.map(|t| t.from_label.clone())),
+        "to_label" => optional_strings(column, transfers.map(|t| t.to_label.clone())),
+        other => Err(anyhow!("No values for Parquet column {}", other)),
+    }
+}
+
+fn required_strings(column: &mut SerializedColumnWriter, values: impl Iterator<Item = String>) -> Result<()> {
+    let values: Vec<ByteArray> = values.map(|v| ByteArray::from(v.as_str())).collect();
+    column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+    Ok(())
+}
+
+// Null values are left out of the values and marked by definition level 0
+fn optional_strings(column: &mut SerializedColumnWriter, values: impl Iterator<Item = Option<String>>) -> Result<()> {
+    let (definitions, values): (Vec<i16>, Vec<Option<ByteArray>>) =
+        values.map(|v| (v.is_some() as i16, v.map(|v| ByteArray::from(v.as_str())))).unzip();
+    let values: Vec<ByteArray> = values.into_iter().flatten().collect();
+    column.typed::<ByteArrayType>().write_batch(&values, Some(&definitions), None)?;
+    Ok(())
+}
+
+fn optional_int32s(column: &mut SerializedColumnWriter, values: impl Iterator<Item = Option<i32>>) -> Result<()> {
+    let (definitions, values): (Vec<i16>, Vec<Option<i32>>) = values.map(|v| (v.is_some() as i16, v)).unzip();
+    let values: Vec<i32> = values.into_iter().flatten().collect();
+    column.typed::<Int32Type>().write_batch(&values, Some(&definitions), None)?;
+    Ok(())
+}
+
+fn int64s(column: &mut SerializedColumnWriter, values: impl Iterator<Item = i64>) -> Result<()> {
+    let values: Vec<i64> = values.collect();
+    column.typed::<Int64Type>().write_batch(&values, None, None)?;
+    Ok(())
+}
+
+// Repetition level 0 starts a new row and 1 continues it; an empty list is a
+// single entry with definition level 0
+fn repeated_strings(column: &mut SerializedColumnWriter, rows: impl Iterator<Item = Vec<String>>) -> Result<()> {
+    let mut values = Vec::new();
+    let mut definitions = Vec::new();
+    let mut repetitions = Vec::new();
+
+    for row in rows {
+        if row.is_empty() {
+            definitions.push(0);
+            repetitions.push(0);
+        }
+        for (i, value) in row.into_iter().enumerate() {
+            values.push(ByteArray::from(value.as_str()));
+            definitions.push(1);
+            repetitions.push((i > 0) as i16);
+        }
+    }
+
+    column.typed::<ByteArrayType>().write_batch(&values, Some(&definitions), Some(&repetitions))?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, TimeZone, Utc};
-use std::str::FromStr;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::{HashMap, HashSet}, str::FromStr};
 
+use crate::balance::TouchedAccounts;
+use crate::errors::TransactionError;
+use crate::instructions::{DecodedTokenInstruction, InstructionDecoder, RawInstruction, TokenOp};
+use crate::trace::label_path;
 use crate::types::*;
 
 pub struct TransactionParser;
@@ -9,7 +14,7 @@ pub struct TransactionParser;
 impl TransactionParser {
     pub fn parse_usdc_transfers(
         tx: &TransactionResponse,
-        target_wallet: &str,
+        target_wallet: &Pubkey,
     ) -> Result<Vec<UsdcTransfer>> {
         let mut transfers = Vec::new();
         
@@ -22,312 +27,426 @@ impl TransactionParser {
             return Ok(transfers);
         }
 
-        let timestamp = tx.block_time
-            .map(|bt| Utc.timestamp_opt(bt, 0).single().unwrap_or(Utc::now()))
-            .unwrap_or(Utc::now());
+        let timestamp = Self::block_timestamp(tx)?;
 
         let signature = tx.transaction.signatures.first()
             .ok_or_else(|| anyhow!("No signature found"))?
             .clone();
 
+        let account_keys = InstructionDecoder::account_keys(tx);
+        let token_instructions = InstructionDecoder::decode_token_instructions(tx);
+
         // Parse token balance changes
         if let Some(meta) = &tx.meta {
-            transfers.extend(Self::parse_token_balance_changes(
+            let fee_payer = account_keys.first().cloned();
+            let changes = Self::parse_token_balance_changes(
                 meta,
-                &tx.transaction.message.account_keys,
+                &account_keys,
+                &token_instructions,
                 target_wallet,
                 &signature,
                 timestamp,
-            )?);
+                tx.slot,
+            )?;
+            transfers.extend(changes.into_iter().map(|t| UsdcTransfer {
+                fee_lamports: Some(meta.fee),
+                fee_payer: fee_payer.clone(),
+                ..t
+            }));
         }
 
         Ok(transfers)
     }
 
-    fn parse_token_balance_changes(
-        meta: &TransactionMeta,
-        account_keys: &[String],
-        target_wallet: &str,
-        signature: &str,
-        timestamp: DateTime<Utc>,
+    // Block time of the transaction; the indexer resolves missing ones via getBlockTime
+    fn block_timestamp(tx: &TransactionResponse) -> Result<DateTime<Utc>> {
+        let block_time = tx.block_time
+            .ok_or_else(|| anyhow!("No block time for slot {}", tx.slot))?;
+
+        Utc.timestamp_opt(block_time, 0)
+            .single()
+            .ok_or_else(|| anyhow!("Invalid block time {} for slot {}", block_time, tx.slot))
+    }
+
+    // Transfer attempts involving the wallet in a failed transaction
+    pub fn parse_failed_transfers(
+        tx: &TransactionResponse,
+        target_wallet: &Pubkey,
     ) -> Result<Vec<UsdcTransfer>> {
         let mut transfers = Vec::new();
 
-        let pre_balances = meta.pre_token_balances.as_ref().unwrap_or(&vec![]);
-        let post_balances = meta.post_token_balances.as_ref().unwrap_or(&vec![]);
+        let (meta, err) = match &tx.meta {
+            Some(meta) => match &meta.err {
+                Some(err) => (meta, err),
+                None => return Ok(transfers),
+            },
+            None => return Ok(transfers),
+        };
 
-        // Find USDC token accounts
-        for post_balance in post_balances {
-            if post_balance.mint != USDC_MINT {
+        let timestamp = Self::block_timestamp(tx)?;
+
+        let signature = tx.transaction.signatures.first()
+            .ok_or_else(|| anyhow!("No signature found"))?;
+
+        let error = TransactionError::decode(err, meta.log_messages.as_deref());
+        let account_keys = InstructionDecoder::account_keys(tx);
+        let wallet_accounts = Self::wallet_usdc_accounts(meta, &account_keys, target_wallet);
+        let owner = target_wallet.to_string();
+
+        // Owners of every USDC token account in the transaction
+        let owners: HashMap<&str, &str> = meta.pre_token_balances
+            .iter()
+            .chain(meta.post_token_balances.iter())
+            .flatten()
+            .filter(|b| b.mint == USDC_MINT)
+            .filter_map(|b| {
+                let address = account_keys.get(b.account_index as usize)?;
+                Some((address.as_str(), b.owner.as_deref()?))
+            })
+            .collect();
+
+        for ix in InstructionDecoder::decode_token_instructions(tx) {
+            let amount = match ix.op {
+                TokenOp::Transfer { amount } | TokenOp::TransferChecked { amount, .. } => amount,
+                _ => continue,
+            };
+            let (Some(source), Some(destination)) = (ix.source(), ix.destination()) else {
                 continue;
-            }
+            };
+
+            let (is_incoming, decimals) = match (wallet_accounts.get(source), wallet_accounts.get(destination)) {
+                (Some(decimals), _) => (false, decimals),
+                (None, Some(decimals)) => (true, decimals),
+                (None, None) => continue,
+            };
+
+            let owner_of = |account: &str| owners.get(account).map(|o| o.to_string())
+                .unwrap_or_else(|| account.to_string());
+
+            let authority = ix.authority().map(str::to_string);
+            let authority_kind = if is_incoming {
+                None
+            } else {
+                authority.as_ref().map(|a| {
+                    if *a == owner { AuthorityKind::Owner } else { AuthorityKind::Delegate }
+                })
+            };
+
+            transfers.push(UsdcTransfer {
+                signature: signature.clone(),
+                timestamp,
+                from_address: owner_of(source),
+                to_address: owner_of(destination),
+                amount: amount as f64 / 10f64.powi(*decimals as i32),
+                is_incoming,
+                raw_amount: amount,
+                decimals: *decimals,
+                slot: tx.slot,
+                tx_index: None,
+                instruction_index: Some(ix.instruction_index),
+                inner_index: ix.inner_index,
+                token_account: Some(if is_incoming { destination } else { source }.to_string()),
+                finality: None,
+                is_internal: false,
+                authority: if is_incoming { None } else { authority },
+                authority_kind,
+                error: Some(error.clone()),
+                program_path: label_path(&ix.program_path),
+                fee_lamports: Some(meta.fee),
+                fee_payer: account_keys.first().cloned(),
+                balance_after: None,
+                from_label: None,
+                to_label: None,
+            });
+        }
 
-            // Find corresponding pre-balance
-            let pre_balance = pre_balances.iter()
-                .find(|pb| pb.account_index == post_balance.account_index);
+        Ok(transfers)
+    }
 
-            let pre_amount = pre_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
-            
-            let post_amount = post_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-            let amount_change = post_amount - pre_amount;
+    pub fn parse_delegation_events(
+        tx: &TransactionResponse,
+        target_wallet: &Pubkey,
+    ) -> Result<Vec<DelegationEvent>> {
+        let mut events = Vec::new();
 
-            if amount_change.abs() < 0.000001 {
-                continue; // No significant change
-            }
+        let meta = match &tx.meta {
+            Some(meta) if meta.err.is_none() => meta,
+            _ => return Ok(events),
+        };
 
-            // Get the owner of the token account
-            let owner = post_balance.owner.as_ref()
-                .or_else(|| {
-                    // Fallback: try to find the owner in account keys
-                    if post_balance.account_index > 0 {
-                        account_keys.get((post_balance.account_index - 1) as usize)
-                    } else {
-                        use anyhow::{anyhow, Result};
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use chrono::{DateTime, TimeZone, Utc};
-use solana_sdk::pubkey::Pubkey;
-use std::str::FromStr;
+        let timestamp = Self::block_timestamp(tx)?;
 
-use crate::types::*;
+        let signature = tx.transaction.signatures.first()
+            .ok_or_else(|| anyhow!("No signature found"))?;
 
-pub struct TransactionParser;
+        let account_keys = InstructionDecoder::account_keys(tx);
+        let wallet_accounts = Self::wallet_usdc_accounts(meta, &account_keys, target_wallet);
+        let owner = target_wallet.to_string();
 
-impl TransactionParser {
-    pub fn parse_usdc_transfers(
-        tx: &TransactionResponse,
-        target_wallet: &Pubkey,
-    ) -> Result<Vec<UsdcTransfer>> {
-        let mut transfers = Vec::new();
-        
-        // Skip failed transactions
-        if let Some(meta) = &tx.meta {
-            if meta.err.is_some() {
-                return Ok(transfers);
-            }
-        } else {
-            return Ok(transfers);
-        }
+        for ix in InstructionDecoder::decode_token_instructions(tx) {
+            let Some(token_account) = ix.source() else { continue };
+            let Some(decimals) = wallet_accounts.get(token_account) else { continue };
 
-        let timestamp = tx.block_time
-            .map(|bt| Utc.timestamp_opt(bt, 0).single().unwrap_or(Utc::now()))
-            .unwrap_or(Utc::now());
-
-        let signature = tx.transaction.signatures.first()
-            .ok_or_else(|| anyhow!("No signature found"))?
-            .clone();
+            let (kind, delegate, raw_amount) = match ix.op {
+                TokenOp::Approve { amount } | TokenOp::ApproveChecked { amount, .. } => {
+                    (DelegationKind::Approve, ix.delegate(), amount)
+                }
+                TokenOp::Revoke => (DelegationKind::Revoke, None, 0),
+                TokenOp::Transfer { amount } | TokenOp::TransferChecked { amount, .. } => {
+                    match ix.authority() {
+                        Some(authority) if authority != owner => {
+                            (DelegationKind::DelegatedSpend, Some(authority), amount)
+                        }
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
 
-        // Parse token balance changes
-        if let Some(meta) = &tx.meta {
-            transfers.extend(Self::parse_token_balance_changes(
-                meta,
-                &tx.transaction.message.account_keys,
-                target_wallet,
-                &signature,
+            events.push(DelegationEvent {
+                signature: signature.clone(),
                 timestamp,
-            )?);
+                slot: tx.slot,
+                tx_index: None,
+                instruction_index: ix.instruction_index,
+                token_account: token_account.to_string(),
+                kind,
+                delegate: delegate.map(str::to_string),
+                amount: raw_amount as f64 / 10f64.powi(*decimals as i32),
+                allowance: None,
+            });
         }
 
-        Ok(transfers)
+        Ok(events)
     }
 
-    fn parse_token_balance_changes(
-        meta: &TransactionMeta,
-        account_keys: &[String],
+    pub fn parse_lifecycle_events(
+        tx: &TransactionResponse,
         target_wallet: &Pubkey,
-        signature: &str,
-        timestamp: DateTime<Utc>,
-    ) -> Result<Vec<UsdcTransfer>> {
-        let mut transfers = Vec::new();
+    ) -> Result<Vec<LifecycleEvent>> {
+        let mut events = Vec::new();
 
-        // Fix the temporary value issue by creating bindings
-        let empty_pre_balances = vec![];
-        let empty_post_balances = vec![];
-        
-        let pre_balances = meta.pre_token_balances.as_ref().unwrap_or(&empty_pre_balances);
-        let post_balances = meta.post_token_balances.as_ref().unwrap_or(&empty_post_balances);
+        let meta = match &tx.meta {
+            Some(meta) if meta.err.is_none() => meta,
+            _ => return Ok(events),
+        };
 
-        // Find USDC token accounts
-        let usdc_mint = Pubkey::from_str(USDC_MINT)?;
+        let timestamp = Self::block_timestamp(tx)?;
 
-        for post_balance in post_balances {
-            if post_balance.mint != USDC_MINT {
+        let signature = tx.transaction.signatures.first()
+            .ok_or_else(|| anyhow!("No signature found"))?;
+
+        let account_keys = InstructionDecoder::account_keys(tx);
+        let instructions = InstructionDecoder::instructions(tx);
+        let wallet_accounts = Self::wallet_usdc_accounts(meta, &account_keys, target_wallet);
+        let owner = target_wallet.to_string();
+
+        let lamports = |address: &str, balances: &[u64]| {
+            account_keys.iter()
+                .position(|key| key == address)
+                .and_then(|i| balances.get(i).copied())
+        };
+
+        let event = |instruction_index: usize, token_account: &str, kind: LifecycleKind| LifecycleEvent {
+            signature: signature.clone(),
+            timestamp,
+            slot: tx.slot,
+            tx_index: None,
+            instruction_index,
+            token_account: token_account.to_string(),
+            kind,
+            rent_lamports: None,
+            rent_payer: None,
+            rent_recipient: None,
+            authority: None,
+            new_owner: None,
+        };
+
+        // Associated token accounts created for the wallet. The token program's
+        // InitializeAccount3 runs inside these, so it is not reported separately.
+        let mut created = HashSet::new();
+        for ix in instructions.iter().filter(|ix| ix.program_id == ASSOCIATED_TOKEN_PROGRAM_ID) {
+            // Create has empty data or 0, CreateIdempotent is 1
+            if !matches!(ix.data.first(), None | Some(0) | Some(1)) {
                 continue;
             }
 
-            // Find corresponding pre-balance
-            let pre_balance = pre_balances.iter()
-                .find(|pb| pb.account_index == post_balance.account_index);
-
-            let pre_amount = pre_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
-            
-            let post_amount = post_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-            let amount_change = post_amount - pre_amount;
+            let (Some(funder), Some(token_account), Some(wallet), Some(mint)) =
+                (ix.accounts.first(), ix.accounts.get(1), ix.accounts.get(2), ix.accounts.get(3))
+            else {
+                continue;
+            };
+            if *wallet != owner || mint != USDC_MINT {
+                continue;
+            }
 
-            if amount_change.abs() < 0.000001 {
-                continue; // No significant change
+            // CreateIdempotent on an account that already existed creates nothing
+            if lamports(token_account, &meta.pre_balances).unwrap_or(0) > 0 {
+                continue;
             }
 
-            // Get the owner of the token account
-            let account_address = account_keys.get(post_balance.account_index as usize)
-                .ok_or_else(|| anyhow!("Account index out of bounds"))?;
+            created.insert(token_account.clone());
+            events.push(LifecycleEvent {
+                rent_lamports: lamports(token_account, &meta.post_balances),
+                rent_payer: Some(funder.clone()),
+                ..event(ix.instruction_index, token_account, LifecycleKind::CreateAssociated)
+            });
+        }
 
-            // Try to determine the owner from the token balance or account keys
-            let owner = post_balance.owner.as_ref()
-                .or_else(|| {
-                    // Fallback: try to find the owner in account keys
-                    // This is a simplified approach
-                    if post_balance.account_index > 0 {
-                        account_keys.get((post_balance.account_index - 1) as usize)
-                    } else {
-                        None
+        for ix in InstructionDecoder::decode_token_instructions(tx) {
+            let Some(token_account) = ix.source() else { continue };
+
+            match &ix.op {
+                TokenOp::InitializeAccount { .. } => {
+                    if ix.mint() != Some(USDC_MINT)
+                        || ix.initialized_owner() != Some(owner.as_str())
+                        || created.contains(token_account)
+                    {
+                        continue;
                     }
-                });
 
-            if let Some(owner_str) = owner {
-                let owner_pubkey = Pubkey::from_str(owner_str).ok();
-                
-                if let Some(owner_pubkey) = owner_pubkey {
-                    if owner_pubkey == *target_wallet {
-                        // This is our target wallet's token account
-                        if amount_change > 0.0 {
-                            // Received tokens - need to find sender
-                            let from_address = Self::find_sender_address(
-                                meta, account_keys, post_balance.account_index
-                            ).unwrap_or_else(|| "Unknown".to_string());
+                    let rent_payer = Self::find_account_funder(&instructions, token_account)
+                        .or_else(|| account_keys.first().cloned());
 
-                            transfers.push(UsdcTransfer {
-                                signature: signature.to_string(),
-                                timestamp,
-                                from_address,
-                                to_address: target_wallet.to_string(),
-                                amount: amount_change,
-                                is_incoming: true,
-                            });
-                        } else if amount_change < 0.0 {
-                            // Sent tokens - need to find recipient
-                            let to_address = Self::find_recipient_address(
-                                meta, account_keys, post_balance.account_index
-                            ).unwrap_or_else(|| "Unknown".to_string());
+                    events.push(LifecycleEvent {
+                        rent_lamports: lamports(token_account, &meta.post_balances),
+                        rent_payer,
+                        ..event(ix.instruction_index, token_account, LifecycleKind::Initialize)
+                    });
+                }
+                TokenOp::CloseAccount if wallet_accounts.contains_key(token_account) => {
+                    events.push(LifecycleEvent {
+                        rent_lamports: lamports(token_account, &meta.pre_balances),
+                        rent_recipient: ix.close_destination().map(str::to_string),
+                        authority: ix.authority().map(str::to_string),
+                        ..event(ix.instruction_index, token_account, LifecycleKind::Close)
+                    });
+                }
+                TokenOp::FreezeAccount | TokenOp::ThawAccount
+                    if wallet_accounts.contains_key(token_account) =>
+                {
+                    let kind = if ix.op == TokenOp::FreezeAccount {
+                        LifecycleKind::Freeze
+                    } else {
+                        LifecycleKind::Thaw
+                    };
 
-                            transfers.push(UsdcTransfer {
-                                signature: signature.to_string(),
-                                timestamp,
-                                from_address: target_wallet.to_string(),
-                                to_address,
-                                amount: amount_change.abs(),
-                                is_incoming: false,
-                            });
-                        }
-                    }
+                    events.push(LifecycleEvent {
+                        authority: ix.authority().map(str::to_string),
+                        ..event(ix.instruction_index, token_account, kind)
+                    });
+                }
+                TokenOp::SetOwner { new_owner } if wallet_accounts.contains_key(token_account) => {
+                    events.push(LifecycleEvent {
+                        authority: ix.authority().map(str::to_string),
+                        new_owner: new_owner.clone(),
+                        ..event(ix.instruction_index, token_account, LifecycleKind::OwnerChange)
+                    });
                 }
+                _ => {}
             }
         }
 
-        Ok(transfers)
-    }
-
-    fn find_sender_address(
-        meta: &TransactionMeta,
-        account_keys: &[String],
-        target_account_index: u8,
-    ) -> Option<String> {
-        // Look through pre-token balances to find who had a decrease
-        let pre_balances = meta.pre_token_balances.as_ref()?;
-        let post_balances = meta.post_token_balances.as_ref()?;
-
-        for pre_balance in pre_balances {
-            if pre_balance.mint != USDC_MINT || pre_balance.account_index == target_account_index {
-                continue;
-            }
+        // Keep execution order across the two passes above
+        events.sort_by_key(|e| e.instruction_index);
 
-            let post_balance = post_balances.iter()
-                .find(|pb| pb.account_index == pre_balance.account_index);
+        Ok(events)
+    }
 
-            let pre_amount = pre_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-            let post_amount = post_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
+    // Account that funded a System CreateAccount/CreateAccountWithSeed of the given address
+    fn find_account_funder(instructions: &[RawInstruction], address: &str) -> Option<String> {
+        instructions.iter()
+            .filter(|ix| ix.program_id == SYSTEM_PROGRAM_ID)
+            .filter(|ix| matches!(ix.data.get(..4), Some([0, 0, 0, 0]) | Some([3, 0, 0, 0])))
+            .find(|ix| ix.accounts.get(1).map(String::as_str) == Some(address))
+            .and_then(|ix| ix.accounts.first().cloned())
+    }
 
-            if pre_amount > post_amount {
-                return pre_balance.owner.clone()
-                    .or_else(|| account_keys.get(pre_balance.account_index as usize).cloned());
+    // The wallet's USDC token accounts touched by the transaction, with their
+    // balances before and after it in base units (None where the account did
+    // not exist)
+    pub fn wallet_usdc_account_balances(tx: &TransactionResponse, target_wallet: &Pubkey) -> TouchedAccounts {
+        let owner = target_wallet.to_string();
+        let account_keys = InstructionDecoder::account_keys(tx);
+        let mut touched = TouchedAccounts::new();
+        let Some(meta) = &tx.meta else { return touched };
+
+        let sides = [(&meta.pre_token_balances, false), (&meta.post_token_balances, true)];
+        for (balances, post) in sides {
+            for b in balances.iter().flatten() {
+                if b.mint != USDC_MINT || b.owner.as_deref() != Some(owner.as_str()) {
+                    continue;
+                }
+                let Some(address) = account_keys.get(b.account_index as usize) else { continue };
+                let entry = touched.entry(address.clone()).or_default();
+                let amount = b.ui_token_amount.amount.parse().ok();
+                if post { entry.1 = amount } else { entry.0 = amount }
             }
         }
-
-        None
+        touched
     }
 
-    fn find_recipient_address(
+    // USDC token accounts owned by the wallet, with their decimals
+    fn wallet_usdc_accounts(
         meta: &TransactionMeta,
         account_keys: &[String],
-        sender_account_index: u8,
-    ) -> Option<String> {
-        // Look through post-token balances to find who had an increase
-        let pre_balances = meta.pre_token_balances.as_ref()?;
-        let post_balances = meta.post_token_balances.as_ref()?;
-
-        for post_balance in post_balances {
-            if post_balance.mint != USDC_MINT || post_balance.account_index == sender_account_index {
-                continue;
-            }
-
-            let pre_balance = pre_balances.iter()
-                .find(|pb| pb.account_index == post_balance.account_index);
-
-            let pre_amount = pre_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
-            let post_amount = post_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-
-            if post_amount > pre_amount {
-                return post_balance.owner.clone()
-                    .or_else(|| account_keys.get(post_balance.account_index as usize).cloned());
-            }
-        }
-
-        None
+        target_wallet: &Pubkey,
+    ) -> HashMap<String, u8> {
+        let owner = target_wallet.to_string();
+
+        meta.pre_token_balances
+            .iter()
+            .chain(meta.post_token_balances.iter())
+            .flatten()
+            .filter(|b| b.mint == USDC_MINT && b.owner.as_deref() == Some(owner.as_str()))
+            .filter_map(|b| {
+                account_keys.get(b.account_index as usize)
+                    .map(|address| (address.clone(), b.ui_token_amount.decimals))
+            })
+            .collect()
     }
-} tx.block_time
-            .map(|bt| Utc.timestamp_opt(bt, 0).single().unwrap_or(Utc::now()))
-            .unwrap_or(Utc::now());
-
-        let signature = tx.transaction.signatures.first()
-            .ok_or_else(|| anyhow!("No signature found"))?
-            .clone();
 
-        // Parse token balance changes
-        if let Some(meta) = &tx.meta {
-            transfers.extend(Self::parse_token_balance_changes(
-                meta,
-                &tx.transaction.message.account_keys,
-                target_wallet,
-                &signature,
-                timestamp,
-            )?);
-        }
+    // First transfer into or out of the given token account
+    fn find_movement<'a>(
+        token_instructions: &'a [DecodedTokenInstruction],
+        token_account: &str,
+    ) -> Option<&'a DecodedTokenInstruction> {
+        token_instructions.iter()
+            .filter(|ix| matches!(ix.op, TokenOp::Transfer { .. } | TokenOp::TransferChecked { .. }))
+            .find(|ix| ix.source() == Some(token_account) || ix.destination() == Some(token_account))
+    }
 
-        Ok(transfers)
+    // Signer of the first transfer out of the given token account
+    fn find_transfer_authority(
+        token_instructions: &[DecodedTokenInstruction],
+        token_account: &str,
+    ) -> Option<String> {
+        token_instructions.iter()
+            .filter(|ix| matches!(ix.op, TokenOp::Transfer { .. } | TokenOp::TransferChecked { .. }))
+            .find(|ix| ix.source() == Some(token_account))
+            .and_then(|ix| ix.authority())
+            .map(str::to_string)
     }
 
     fn parse_token_balance_changes(
         meta: &TransactionMeta,
         account_keys: &[String],
+        token_instructions: &[DecodedTokenInstruction],
         target_wallet: &Pubkey,
         signature: &str,
         timestamp: DateTime<Utc>,
+        slot: u64,
     ) -> Result<Vec<UsdcTransfer>> {
         let mut transfers = Vec::new();
 
-        let pre_balances = meta.pre_token_balances.as_ref().unwrap_or(&vec![]);
-        let post_balances = meta.post_token_balances.as_ref().unwrap_or(&vec![]);
+        // Fix the temporary value issue by creating bindings
+        let empty_pre_balances = vec![];
+        let empty_post_balances = vec![];
+        
+        let pre_balances = meta.pre_token_balances.as_ref().unwrap_or(&empty_pre_balances);
+        let post_balances = meta.post_token_balances.as_ref().unwrap_or(&empty_post_balances);
 
         // Find USDC token accounts
-        let usdc_mint = Pubkey::from_str(USDC_MINT)?;
-
         for post_balance in post_balances {
             if post_balance.mint != USDC_MINT {
                 continue;
@@ -348,9 +467,13 @@ impl TransactionParser {
                 continue; // No significant change
             }
 
-            // Get the owner of the token account
-            let account_address = account_keys.get(post_balance.account_index as usize)
-                .ok_or_else(|| anyhow!("Account index out of bounds"))?;
+            // Exact change from the raw base-unit amounts
+            let raw_pre = pre_balance
+                .and_then(|pb| pb.ui_token_amount.amount.parse::<u64>().ok())
+                .unwrap_or(0);
+            let raw_post = post_balance.ui_token_amount.amount.parse::<u64>().unwrap_or(0);
+            let raw_amount = raw_post.abs_diff(raw_pre);
+            let decimals = post_balance.ui_token_amount.decimals;
 
             // Try to determine the owner from the token balance or account keys
             let owner = post_balance.owner.as_ref()
@@ -376,6 +499,10 @@ impl TransactionParser {
                                 meta, account_keys, post_balance.account_index
                             ).unwrap_or_else(|| "Unknown".to_string());
 
+                            let token_account = account_keys.get(post_balance.account_index as usize);
+                            let movement = token_account
+                                .and_then(|account| Self::find_movement(token_instructions, account));
+
                             transfers.push(UsdcTransfer {
                                 signature: signature.to_string(),
                                 timestamp,
@@ -383,6 +510,26 @@ impl TransactionParser {
                                 to_address: target_wallet.to_string(),
                                 amount: amount_change,
                                 is_incoming: true,
+                                raw_amount,
+                                decimals,
+                                slot,
+                                tx_index: None,
+                                instruction_index: movement.map(|ix| ix.instruction_index),
+                                inner_index: movement.and_then(|ix| ix.inner_index),
+                                token_account: token_account.cloned(),
+                                finality: None,
+                                is_internal: false,
+                                authority: None,
+                                authority_kind: None,
+                                error: None,
+                                program_path: movement
+                                    .map(|ix| label_path(&ix.program_path))
+                                    .unwrap_or_default(),
+                                fee_lamports: None,
+                                fee_payer: None,
+                                balance_after: None,
+                                from_label: None,
+                                to_label: None,
                             });
                         } else if amount_change < 0.0 {
                             // Sent tokens - need to find recipient
@@ -390,6 +537,21 @@ impl TransactionParser {
                                 meta, account_keys, post_balance.account_index
                             ).unwrap_or_else(|| "Unknown".to_string());
 
+                            let token_account = account_keys.get(post_balance.account_index as usize);
+                            let movement = token_account
+                                .and_then(|account| Self::find_movement(token_instructions, account));
+
+                            // Who signed the movement: the owner or a delegate
+                            let authority = token_account
+                                .and_then(|account| Self::find_transfer_authority(token_instructions, account));
+                            let authority_kind = authority.as_ref().map(|a| {
+                                if *a == target_wallet.to_string() {
+                                    AuthorityKind::Owner
+                                } else {
+                                    AuthorityKind::Delegate
+                                }
+                            });
+
                             transfers.push(UsdcTransfer {
                                 signature: signature.to_string(),
                                 timestamp,
@@ -397,6 +559,26 @@ impl TransactionParser {
                                 to_address,
                                 amount: amount_change.abs(),
                                 is_incoming: false,
+                                raw_amount,
+                                decimals,
+                                slot,
+                                tx_index: None,
+                                instruction_index: movement.map(|ix| ix.instruction_index),
+                                inner_index: movement.and_then(|ix| ix.inner_index),
+                                token_account: token_account.cloned(),
+                                finality: None,
+                                is_internal: false,
+                                authority,
+                                authority_kind,
+                                error: None,
+                                program_path: movement
+                                    .map(|ix| label_path(&ix.program_path))
+                                    .unwrap_or_default(),
+                                fee_lamports: None,
+                                fee_payer: None,
+                                balance_after: None,
+                                from_label: None,
+                                to_label: None,
                             });
                         }
                     }
@@ -469,107 +651,3 @@ impl TransactionParser {
         None
     }
 }
-                    }
-                });
-
-            if let Some(owner_str) = owner {
-                if owner_str == target_wallet {
-                    // This is our target wallet's token account
-                    if amount_change > 0.0 {
-                        // Received tokens - need to find sender
-                        let from_address = Self::find_sender_address(
-                            meta, account_keys, post_balance.account_index
-                        ).unwrap_or_else(|| "Unknown".to_string());
-
-                        transfers.push(UsdcTransfer {
-                            signature: signature.to_string(),
-                            timestamp,
-                            from_address,
-                            to_address: target_wallet.to_string(),
-                            amount: amount_change,
-                            is_incoming: true,
-                        });
-                    } else if amount_change < 0.0 {
-                        // Sent tokens - need to find recipient
-                        let to_address = Self::find_recipient_address(
-                            meta, account_keys, post_balance.account_index
-                        ).unwrap_or_else(|| "Unknown".to_string());
-
-                        transfers.push(UsdcTransfer {
-                            signature: signature.to_string(),
-                            timestamp,
-                            from_address: target_wallet.to_string(),
-                            to_address,
-                            amount: amount_change.abs(),
-                            is_incoming: false,
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(transfers)
-    }
-
-    fn find_sender_address(
-        meta: &TransactionMeta,
-        account_keys: &[String],
-        target_account_index: u8,
-    ) -> Option<String> {
-        // Look through pre-token balances to find who had a decrease
-        let pre_balances = meta.pre_token_balances.as_ref()?;
-        let post_balances = meta.post_token_balances.as_ref()?;
-
-        for pre_balance in pre_balances {
-            if pre_balance.mint != USDC_MINT || pre_balance.account_index == target_account_index {
-                continue;
-            }
-
-            let post_balance = post_balances.iter()
-                .find(|pb| pb.account_index == pre_balance.account_index);
-
-            let pre_amount = pre_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-            let post_amount = post_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
-
-            if pre_amount > post_amount {
-                return pre_balance.owner.clone()
-                    .or_else(|| account_keys.get(pre_balance.account_index as usize).cloned());
-            }
-        }
-
-        None
-    }
-
-    fn find_recipient_address(
-        meta: &TransactionMeta,
-        account_keys: &[String],
-        sender_account_index: u8,
-    ) -> Option<String> {
-        // Look through post-token balances to find who had an increase
-        let pre_balances = meta.pre_token_balances.as_ref()?;
-        let post_balances = meta.post_token_balances.as_ref()?;
-
-        for post_balance in post_balances {
-            if post_balance.mint != USDC_MINT || post_balance.account_index == sender_account_index {
-                continue;
-            }
-
-            let pre_balance = pre_balances.iter()
-                .find(|pb| pb.account_index == post_balance.account_index);
-
-            let pre_amount = pre_balance
-                .and_then(|pb| pb.ui_token_amount.ui_amount)
-                .unwrap_or(0.0);
-            let post_amount = post_balance.ui_token_amount.ui_amount.unwrap_or(0.0);
-
-            if post_amount > pre_amount {
-                return post_balance.owner.clone()
-                    .or_else(|| account_keys.get(post_balance.account_index as usize).cloned());
-            }
-        }
-
-        None
-    }
-}
\ No newline at end of file
@@ -0,0 +1,556 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use tokio_postgres::{types::ToSql, Client, NoTls};
+use tracing::{error, info};
+
+use crate::checkpoint::{Checkpoint, CheckpointStore};
+use crate::sink::TransferSink;
+use crate::sqlite::to_text;
+use crate::types::*;
+
+// Schema migrations, applied in order. Never edit a released migration;
+// append a new one instead.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (
+        1,
+        "CREATE TABLE wallets (
+            address TEXT PRIMARY KEY,
+            first_indexed_at TIMESTAMPTZ NOT NULL,
+            last_indexed_at TIMESTAMPTZ NOT NULL
+        );
+
+        CREATE TABLE transactions (
+            signature TEXT PRIMARY KEY,
+            slot BIGINT NOT NULL,
+            tx_index INTEGER,
+            block_time TIMESTAMPTZ NOT NULL,
+            finality TEXT,
+            error JSONB
+        );
+
+        CREATE TABLE transfers (
+            id BIGSERIAL PRIMARY KEY,
+            signature TEXT NOT NULL REFERENCES transactions(signature),
+            instruction_index INTEGER NOT NULL,
+            leg TEXT NOT NULL,
+            wallet TEXT NOT NULL,
+            from_address TEXT NOT NULL,
+            to_address TEXT NOT NULL,
+            amount DOUBLE PRECISION NOT NULL,
+            is_incoming BOOLEAN NOT NULL,
+            is_internal BOOLEAN NOT NULL,
+            timestamp TIMESTAMPTZ NOT NULL,
+            slot BIGINT NOT NULL,
+            tx_index INTEGER,
+            finality TEXT,
+            authority TEXT,
+            authority_kind TEXT,
+            error JSONB,
+            program_path JSONB NOT NULL,
+            UNIQUE (signature, instruction_index, leg)
+        );
+
+        CREATE INDEX transfers_wallet_time ON transfers (wallet, timestamp DESC);
+        CREATE INDEX transfers_from_address ON transfers (from_address, timestamp DESC);
+        CREATE INDEX transfers_to_address ON transfers (to_address, timestamp DESC);
+
+        CREATE TABLE runs (
+            id BIGSERIAL PRIMARY KEY,
+            wallet TEXT NOT NULL,
+            started_at TIMESTAMPTZ NOT NULL,
+            finished_at TIMESTAMPTZ,
+            hours_back BIGINT NOT NULL,
+            transfers_found BIGINT
+        );",
+    ),
+    (
+        2,
+        "CREATE TABLE checkpoints (
+            wallet TEXT PRIMARY KEY,
+            signature TEXT NOT NULL,
+            slot BIGINT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL
+        );",
+    ),
+    (
+        3,
+        "ALTER TABLE transfers
+            ADD COLUMN raw_amount BIGINT NOT NULL DEFAULT 0,
+            ADD COLUMN decimals SMALLINT NOT NULL DEFAULT 6;
+        UPDATE transfers SET raw_amount = ROUND(amount * 1000000)::BIGINT;",
+    ),
+    (
+        4,
+        "ALTER TABLE transfers
+            ADD COLUMN fee_lamports BIGINT,
+            ADD COLUMN fee_payer TEXT;",
+    ),
+    (5, "ALTER TABLE transfers ADD COLUMN balance_after BIGINT;"),
+    (
+        6,
+        "ALTER TABLE transfers ADD COLUMN inner_index INTEGER NOT NULL DEFAULT -1;
+        ALTER TABLE transfers DROP CONSTRAINT transfers_signature_instruction_index_leg_key;
+        ALTER TABLE transfers ADD CONSTRAINT transfers_signature_instruction_index_inner_index_leg_key
+            UNIQUE (signature, instruction_index, inner_index, leg);",
+    ),
+    (
+        7,
+        // The wallet's token account joins the unique key; older rows keep
+        // an empty account until re-indexed, see `save_transfers`
+        "ALTER TABLE transfers ADD COLUMN token_account TEXT NOT NULL DEFAULT '';
+        ALTER TABLE transfers DROP CONSTRAINT transfers_signature_instruction_index_inner_index_leg_key;
+        ALTER TABLE transfers ADD CONSTRAINT transfers_leg_key
+            UNIQUE (signature, instruction_index, inner_index, leg, token_account);",
+    ),
+];
+
+// Arbitrary key for the advisory lock that serializes migrations across
+// services sharing the database
+const MIGRATION_LOCK: i64 = 0x5553_4443_4944_5852;
+
+// Rows per multi-row INSERT; keeps the bind parameter count well below
+// Postgres' limit of 65535
+const INSERT_BATCH_SIZE: usize = 500;
+
+// Transfer rows without an instruction index, and top-level transfers
+// without an inner index, are stored under -1
+const UNKNOWN_INSTRUCTION: i32 = -1;
+
+// PostgreSQL database of indexed transfers, shared between services
+pub struct PostgresStore {
+    client: Client,
+    // Run being recorded while used as a sink
+    run_id: Option<i64>,
+}
+
+impl PostgresStore {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .map_err(|e| anyhow!("Failed to connect to Postgres: {}", e))?;
+
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!(error = %e, "Postgres connection error");
+            }
+        });
+
+        let mut store = Self { client, run_id: None };
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    async fn migrate(&mut self) -> Result<()> {
+        let tx = self.client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK]).await?;
+        tx.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL
+            )",
+        )
+        .await?;
+
+        let current: i64 = tx
+            .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+            .await?
+            .get(0);
+
+        for (version, sql) in MIGRATIONS.iter().filter(|(v, _)| *v > current) {
+            tx.batch_execute(sql).await?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)",
+                &[version, &Utc::now()],
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Record the start of an indexing run, returning its id
+    pub async fn begin_run(&self, wallet: &str, hours_back: u64) -> Result<i64> {
+        let row = self
+            .client
+            .query_one(
+                "INSERT INTO runs (wallet, started_at, hours_back) VALUES ($1, $2, $3) RETURNING id",
+                &[&wallet, &Utc::now(), &(hours_back as i64)],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    pub async fn finish_run(&self, run_id: i64, transfers_found: usize) -> Result<()> {
+        self.client
+            .execute(
+                "UPDATE runs SET finished_at = $1, transfers_found = $2 WHERE id = $3",
+                &[&Utc::now(), &(transfers_found as i64), &run_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Upsert a processed batch of a wallet's transfers in one transaction,
+    // using multi-row inserts; re-running over an overlapping range updates
+    // the existing rows instead of duplicating them
+    pub async fn save_transfers(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        let now = Utc::now();
+        let tx = self.client.transaction().await?;
+
+        tx.execute(
+            "INSERT INTO wallets (address, first_indexed_at, last_indexed_at) VALUES ($1, $2, $2)
+             ON CONFLICT (address) DO UPDATE SET last_indexed_at = excluded.last_indexed_at",
+            &[&wallet, &now],
+        )
+        .await?;
+
+        // A multi-row upsert may not touch the same row twice, so collapse
+        // duplicates first, keeping the last one as the SQLite store would
+        let transactions: BTreeMap<&str, TransactionRow> = transfers
+            .iter()
+            .map(|t| (t.signature.as_str(), TransactionRow::new(t)))
+            .collect();
+        let transactions: Vec<_> = transactions.into_values().collect();
+        let rows: BTreeMap<(&str, i32, i32, String, String), TransferRow> = transfers
+            .iter()
+            .map(|t| {
+                let row = TransferRow::new(wallet, t);
+                let key = (
+                    t.signature.as_str(),
+                    row.instruction_index,
+                    row.inner_index,
+                    row.leg.clone(),
+                    row.token_account.clone(),
+                );
+                (key, row)
+            })
+            .collect();
+        let rows: Vec<_> = rows.into_values().collect();
+
+        for batch in transactions.chunks(INSERT_BATCH_SIZE) {
+            let params: Vec<&(dyn ToSql + Sync)> = batch.iter().flat_map(TransactionRow::params).collect();
+            let sql = format!(
+                "INSERT INTO transactions (signature, slot, tx_index, block_time, finality, error)
+                 VALUES {}
+                 ON CONFLICT (signature) DO UPDATE SET
+                    tx_index = COALESCE(excluded.tx_index, transactions.tx_index),
+                    finality = COALESCE(excluded.finality, transactions.finality)",
+                placeholders(batch.len(), TransactionRow::COLUMNS)
+            );
+            tx.execute(sql.as_str(), &params).await?;
+        }
+
+        // Rows saved before the token account was recorded are claimed by a
+        // leg with the same key, so re-indexing updates them instead of
+        // adding a copy
+        let legacy: Vec<_> = rows.iter().filter(|r| !r.token_account.is_empty()).collect();
+        if !legacy.is_empty() {
+            let signatures: Vec<&str> = legacy.iter().map(|r| r.signature.as_str()).collect();
+            let instruction_indexes: Vec<i32> = legacy.iter().map(|r| r.instruction_index).collect();
+            let inner_indexes: Vec<i32> = legacy.iter().map(|r| r.inner_index).collect();
+            let legs: Vec<&str> = legacy.iter().map(|r| r.leg.as_str()).collect();
+            let accounts: Vec<&str> = legacy.iter().map(|r| r.token_account.as_str()).collect();
+            tx.execute(
+                "UPDATE transfers t SET token_account = u.token_account
+                 FROM unnest($1::text[], $2::int[], $3::int[], $4::text[], $5::text[])
+                    AS u (signature, instruction_index, inner_index, leg, token_account)
+                 WHERE t.signature = u.signature AND t.instruction_index = u.instruction_index
+                    AND t.inner_index = u.inner_index AND t.leg = u.leg AND t.token_account = ''
+                    AND NOT EXISTS (
+                        SELECT 1 FROM transfers e
+                        WHERE e.signature = u.signature AND e.instruction_index = u.instruction_index
+                            AND e.inner_index = u.inner_index AND e.leg = u.leg
+                            AND e.token_account = u.token_account
+                    )",
+                &[&signatures, &instruction_indexes, &inner_indexes, &legs, &accounts],
+            )
+            .await?;
+        }
+
+        for batch in rows.chunks(INSERT_BATCH_SIZE) {
+            let params: Vec<&(dyn ToSql + Sync)> = batch.iter().flat_map(TransferRow::params).collect();
+            let sql = format!(
+                "INSERT INTO transfers (
+                    signature, instruction_index, leg, wallet, from_address, to_address,
+                    amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+                    authority, authority_kind, error, program_path, raw_amount, decimals,
+                    fee_lamports, fee_payer, balance_after, inner_index, token_account
+                 ) VALUES {}
+                 ON CONFLICT (signature, instruction_index, inner_index, leg, token_account) DO UPDATE SET
+                    from_address = excluded.from_address,
+                    to_address = excluded.to_address,
+                    amount = excluded.amount,
+                    raw_amount = excluded.raw_amount,
+                    decimals = excluded.decimals,
+                    is_internal = excluded.is_internal,
+                    tx_index = COALESCE(excluded.tx_index, transfers.tx_index),
+                    finality = COALESCE(excluded.finality, transfers.finality),
+                    authority = excluded.authority,
+                    authority_kind = excluded.authority_kind,
+                    error = excluded.error,
+                    program_path = excluded.program_path,
+                    fee_lamports = COALESCE(excluded.fee_lamports, transfers.fee_lamports),
+                    fee_payer = COALESCE(excluded.fee_payer, transfers.fee_payer),
+                    balance_after = COALESCE(excluded.balance_after, transfers.balance_after)",
+                placeholders(batch.len(), TransferRow::COLUMNS)
+            );
+            tx.execute(sql.as_str(), &params).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferSink for PostgresStore {
+    async fn begin(&mut self, wallet: &str, hours_back: u64) -> Result<()> {
+        self.run_id = Some(self.begin_run(wallet, hours_back).await?);
+        Ok(())
+    }
+
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.save_transfers(wallet, transfers).await
+    }
+
+    async fn finish(&mut self, wallet: &str, transfers_found: usize) -> Result<()> {
+        if let Some(run_id) = self.run_id.take() {
+            self.finish_run(run_id, transfers_found).await?;
+        }
+        info!(wallet, transfers = transfers_found, "Saved transfers to Postgres");
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for PostgresStore {
+    async fn load_checkpoint(&mut self, wallet: &str) -> Result<Option<Checkpoint>> {
+        let row = self
+            .client
+            .query_opt("SELECT signature, slot FROM checkpoints WHERE wallet = $1", &[&wallet])
+            .await?;
+        Ok(row.map(|row| Checkpoint {
+            signature: row.get(0),
+            slot: row.get::<_, i64>(1) as u64,
+        }))
+    }
+
+    async fn save_checkpoint(&mut self, wallet: &str, checkpoint: &Checkpoint) -> Result<()> {
+        self.client
+            .execute(
+                "INSERT INTO checkpoints (wallet, signature, slot, updated_at) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (wallet) DO UPDATE SET
+                    signature = excluded.signature,
+                    slot = excluded.slot,
+                    updated_at = excluded.updated_at",
+                &[&wallet, &checkpoint.signature, &(checkpoint.slot as i64), &Utc::now()],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// Owned column values of one `transactions` row
+struct TransactionRow {
+    signature: String,
+    slot: i64,
+    tx_index: Option<i32>,
+    block_time: chrono::DateTime<Utc>,
+    finality: Option<String>,
+    error: Option<serde_json::Value>,
+}
+
+impl TransactionRow {
+    const COLUMNS: usize = 6;
+
+    fn new(transfer: &UsdcTransfer) -> Self {
+        Self {
+            signature: transfer.signature.clone(),
+            slot: transfer.slot as i64,
+            tx_index: transfer.tx_index.map(|i| i as i32),
+            block_time: transfer.timestamp,
+            finality: to_text(transfer.finality),
+            error: transfer.error.as_ref().and_then(|e| serde_json::to_value(e).ok()),
+        }
+    }
+
+    fn params(&self) -> [&(dyn ToSql + Sync); Self::COLUMNS] {
+        [&self.signature, &self.slot, &self.tx_index, &self.block_time, &self.finality, &self.error]
+    }
+}
+
+// Owned column values of one `transfers` row
+struct TransferRow {
+    signature: String,
+    instruction_index: i32,
+    leg: String,
+    wallet: String,
+    from_address: String,
+    to_address: String,
+    amount: f64,
+    is_incoming: bool,
+    is_internal: bool,
+    timestamp: chrono::DateTime<Utc>,
+    slot: i64,
+    tx_index: Option<i32>,
+    finality: Option<String>,
+    authority: Option<String>,
+    authority_kind: Option<String>,
+    error: Option<serde_json::Value>,
+    program_path: serde_json::Value,
+    raw_amount: i64,
+    decimals: i16,
+    fee_lamports: Option<i64>,
+    fee_payer: Option<String>,
+    balance_after: Option<i64>,
+    inner_index: i32,
+    token_account: String,
+}
+
+impl TransferRow {
+    const COLUMNS: usize = 24;
+
+    fn new(wallet: &str, transfer: &UsdcTransfer) -> Self {
+        let direction = if transfer.is_incoming { "in" } else { "out" };
+
+        Self {
+            signature: transfer.signature.clone(),
+            instruction_index: transfer.instruction_index.map(|i| i as i32).unwrap_or(UNKNOWN_INSTRUCTION),
+            // Which side of the transfer the wallet is on, e.g. "out:<wallet>"
+            leg: format!("{}:{}", direction, wallet),
+            wallet: wallet.to_string(),
+            from_address: transfer.from_address.clone(),
+            to_address: transfer.to_address.clone(),
+            amount: transfer.amount,
+            is_incoming: transfer.is_incoming,
+            is_internal: transfer.is_internal,
+            timestamp: transfer.timestamp,
+            slot: transfer.slot as i64,
+            tx_index: transfer.tx_index.map(|i| i as i32),
+            finality: to_text(transfer.finality),
+            authority: transfer.authority.clone(),
+            authority_kind: to_text(transfer.authority_kind),
+            error: transfer.error.as_ref().and_then(|e| serde_json::to_value(e).ok()),
+            program_path: serde_json::json!(transfer.program_path),
+            raw_amount: transfer.raw_amount as i64,
+            decimals: transfer.decimals as i16,
+            fee_lamports: transfer.fee_lamports.map(|f| f as i64),
+            fee_payer: transfer.fee_payer.clone(),
+            balance_after: transfer.balance_after.map(|b| b as i64),
+            inner_index: transfer.inner_index.map(|i| i as i32).unwrap_or(UNKNOWN_INSTRUCTION),
+            // Empty where unknown, as for rows saved before it was recorded
+            token_account: transfer.token_account.clone().unwrap_or_default(),
+        }
+    }
+
+    fn params(&self) -> [&(dyn ToSql + Sync); Self::COLUMNS] {
+        [
+            &self.signature,
+            &self.instruction_index,
+            &self.leg,
+            &self.wallet,
+            &self.from_address,
+            &self.to_address,
+            &self.amount,
+            &self.is_incoming,
+            &self.is_internal,
+            &self.timestamp,
+            &self.slot,
+            &self.tx_index,
+            &self.finality,
+            &self.authority,
+            &self.authority_kind,
+            &self.error,
+            &self.program_path,
+            &self.raw_amount,
+            &self.decimals,
+            &self.fee_lamports,
+            &self.fee_payer,
+            &self.balance_after,
+            &self.inner_index,
+            &self.token_account,
+        ]
+    }
+}
+
+// "($1, $2), ($3, $4)" for 2 rows of 2 columns
+fn placeholders(rows: usize, columns: usize) -> String {
+    (0..rows)
+        .map(|row| {
+            let values: Vec<String> = (1..=columns).map(|c| format!("${}", row * columns + c)).collect();
+            format!("({})", values.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Needs a disposable database: TEST_DATABASE_URL=postgres://... cargo test -- --ignored
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(signature: &str, token_account: &str, raw_amount: u64) -> UsdcTransfer {
+        UsdcTransfer {
+            signature: signature.to_string(),
+            timestamp: Utc::now(),
+            from_address: "Sender".to_string(),
+            to_address: "Wallet".to_string(),
+            amount: raw_amount as f64 / 1e6,
+            is_incoming: true,
+            raw_amount,
+            decimals: USDC_DECIMALS,
+            slot: 1,
+            tx_index: None,
+            // No Transfer instruction found, as for a MintTo
+            instruction_index: None,
+            inner_index: None,
+            token_account: Some(token_account.to_string()),
+            finality: None,
+            is_internal: false,
+            authority: None,
+            authority_kind: None,
+            error: None,
+            program_path: Vec::new(),
+            fee_lamports: None,
+            fee_payer: None,
+            balance_after: None,
+            from_label: None,
+            to_label: None,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn saving_a_page_twice_keeps_one_row_per_leg() -> Result<()> {
+        let url = std::env::var("TEST_DATABASE_URL")?;
+        let mut store = PostgresStore::connect(&url).await?;
+        store.migrate().await?;
+
+        let signature = format!("test-{}", Utc::now().timestamp_nanos_opt().unwrap_or_default());
+        // Two accounts of the wallet credited by the same instruction, and a
+        // repeated leg within the page
+        let page = vec![
+            transfer(&signature, "AccountA", 1_000_000),
+            transfer(&signature, "AccountB", 2_000_000),
+            transfer(&signature, "AccountB", 2_000_000),
+        ];
+        store.save_transfers("Wallet", &page).await?;
+        store.save_transfers("Wallet", &page).await?;
+
+        let count: i64 = store
+            .client
+            .query_one("SELECT COUNT(*) FROM transfers WHERE signature = $1", &[&signature])
+            .await?
+            .get(0);
+
+        store.client.execute("DELETE FROM transfers WHERE signature = $1", &[&signature]).await?;
+        store.client.execute("DELETE FROM transactions WHERE signature = $1", &[&signature]).await?;
+        store.client.execute("DELETE FROM wallets WHERE address = 'Wallet'", &[]).await?;
+
+        assert_eq!(count, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use crate::balance::ui_amount;
+use crate::category::categorize;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    /// Calendar days
+    Daily,
+    /// ISO weeks starting on Monday
+    Weekly,
+    /// Calendar months
+    Monthly,
+}
+
+impl Period {
+    // First day of the bucket a local date falls in
+    fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => date,
+            Period::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    fn next(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => start + Duration::days(1),
+            Period::Weekly => start + Duration::weeks(1),
+            Period::Monthly => start + Months::new(1),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub inflow: f64,
+    pub outflow: f64,
+    pub net: f64,
+    pub count: usize,
+    pub largest: f64,
+    pub counterparties: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    // Local dates in the report's time zone; the end is exclusive
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub totals: Stats,
+    pub categories: BTreeMap<String, Stats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub period: Period,
+    pub time_zone: String,
+    pub buckets: Vec<Bucket>,
+}
+
+// Running totals in base units while transfers are added
+#[derive(Default)]
+struct Totals {
+    inflow: u64,
+    outflow: u64,
+    count: usize,
+    largest: u64,
+    counterparties: BTreeSet<String>,
+}
+
+impl Totals {
+    fn add(&mut self, transfer: &UsdcTransfer) {
+        let counterparty = if transfer.is_incoming { &transfer.from_address } else { &transfer.to_address };
+        if transfer.is_incoming {
+            self.inflow += transfer.raw_amount;
+        } else {
+            self.outflow += transfer.raw_amount;
+        }
+        self.count += 1;
+        self.largest = self.largest.max(transfer.raw_amount);
+        self.counterparties.insert(counterparty.clone());
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            inflow: ui_amount(self.inflow as i128),
+            outflow: ui_amount(self.outflow as i128),
+            net: ui_amount(self.inflow as i128 - self.outflow as i128),
+            count: self.count,
+            largest: ui_amount(self.largest as i128),
+            counterparties: self.counterparties.len(),
+        }
+    }
+}
+
+// Roll transfers up into periods of the given time zone, overall and per
+// primary category. Periods without transfers between the first and last
+// one are kept with zeros so the rollup has no gaps.
+pub fn build<'a>(transfers: impl IntoIterator<Item = &'a UsdcTransfer>, period: Period, tz: Tz) -> Report {
+    let mut buckets: BTreeMap<NaiveDate, (Totals, BTreeMap<&'static str, Totals>)> = BTreeMap::new();
+
+    for transfer in transfers.into_iter().filter(|t| t.moved_funds()) {
+        let start = period.start(local_date(&transfer.timestamp, tz));
+        let (totals, categories) = buckets.entry(start).or_default();
+        totals.add(transfer);
+        categories.entry(categorize(transfer)[0].as_str()).or_default().add(transfer);
+    }
+
+    if let (Some(first), Some(last)) = (buckets.keys().next().copied(), buckets.keys().last().copied()) {
+        let mut start = first;
+        while start < last {
+            buckets.entry(start).or_default();
+            start = period.next(start);
+        }
+    }
+
+    Report {
+        period,
+        time_zone: tz.name().to_string(),
+        buckets: buckets
+            .into_iter()
+            .map(|(start, (totals, categories))| Bucket {
+                start,
+                end: period.next(start),
+                totals: totals.stats(),
+                categories: categories.iter().map(|(c, t)| (c.to_string(), t.stats())).collect(),
+            })
+            .collect(),
+    }
+}
+
+fn local_date(time: &DateTime<Utc>, tz: Tz) -> NaiveDate {
+    time.with_timezone(&tz).date_naive()
+}
+
+// One row per period and category, with an "all" row for the period's totals
+fn rows(report: &Report) -> Vec<(&Bucket, &str, &Stats)> {
+    report
+        .buckets
+        .iter()
+        .flat_map(|bucket| {
+            std::iter::once((bucket, "all", &bucket.totals))
+                .chain(bucket.categories.iter().map(move |(category, stats)| (bucket, category.as_str(), stats)))
+        })
+        .collect()
+}
+
+pub fn write_table(writer: &mut dyn Write, report: &Report) -> Result<()> {
+    writeln!(writer, "📅 {:?} USDC report ({})", report.period, report.time_zone)?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:<10}  {:<9}  {:>16}  {:>16}  {:>17}  {:>5}  {:>14}  {:>5}",
+        "Period", "Category", "Inflow", "Outflow", "Net", "Count", "Largest", "Peers"
+    )?;
+    for (bucket, category, stats) in rows(report) {
+        let period = if category == "all" { bucket.start.to_string() } else { String::new() };
+        writeln!(
+            writer,
+            "{:<10}  {:<9}  {:>16.6}  {:>16.6}  {:>+17.6}  {:>5}  {:>14.6}  {:>5}",
+            period, category, stats.inflow, stats.outflow, stats.net, stats.count, stats.largest, stats.counterparties
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_csv(writer: Box<dyn Write + Send>, report: &Report) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "period_start",
+        "period_end",
+        "time_zone",
+        "category",
+        "inflow",
+        "outflow",
+        "net",
+        "count",
+        "largest",
+        "counterparties",
+    ])?;
+    for (bucket, category, stats) in rows(report) {
+        writer.write_record([
+            bucket.start.to_string(),
+            bucket.end.to_string(),
+            report.time_zone.clone(),
+            category.to_string(),
+            format!("{:.6}", stats.inflow),
+            format!("{:.6}", stats.outflow),
+            format!("{:.6}", stats.net),
+            stats.count.to_string(),
+            format!("{:.6}", stats.largest),
+            stats.counterparties.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,459 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{collections::HashMap, net::SocketAddr, str::FromStr, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, Mutex},
+};
+use tracing::{debug, info, warn};
+
+use crate::addressbook::AddressBook;
+use crate::graphql;
+use crate::indexer::SolanaIndexer;
+use crate::sink::FanOut;
+use crate::sqlite::SqliteStore;
+use crate::types::*;
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+// Without a store, how far back a request may index the chain (or --hours,
+// if longer), and for how long a wallet indexed from the chain is reused
+pub const MAX_CHAIN_HOURS: u64 = 24 * 7;
+const CHAIN_CACHE_SECS: i64 = 60;
+
+// Newly indexed transfers a slow subscriber may fall behind by before it
+// misses some
+const FEED_CAPACITY: usize = 1024;
+
+// SQLite store shared by all requests. rusqlite blocks, so queries run on the
+// blocking thread pool instead of the async runtime.
+#[derive(Clone)]
+pub struct SharedStore(Arc<std::sync::Mutex<SqliteStore>>);
+
+impl SharedStore {
+    pub async fn query<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&mut SqliteStore) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let store = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut store = store.lock().map_err(|_| anyhow!("Store lock poisoned"))?;
+            query(&mut store)
+        })
+        .await?
+    }
+}
+
+// A wallet's transfers indexed from the chain back to `since`
+struct ChainEntry {
+    fetched_at: DateTime<Utc>,
+    since: DateTime<Utc>,
+    transfers: Vec<UsdcTransfer>,
+}
+
+impl ChainEntry {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now - self.fetched_at < Duration::seconds(CHAIN_CACHE_SECS)
+    }
+}
+
+// Shared by all requests. With a store, wallets are answered from the
+// transfers indexed into it; without one they are indexed from the chain,
+// within a capped range and cached for a short while.
+pub struct AppState {
+    pub indexer: SolanaIndexer,
+    pub store: Option<SharedStore>,
+    pub address_book: AddressBook,
+    // Lookback when a request gives no `since`
+    pub default_hours: u64,
+    // Transfers as they appear in the store, for GraphQL subscriptions
+    pub feed: broadcast::Sender<IndexedTransfer>,
+    // Locked per wallet while it is fetched, so concurrent requests share one fetch
+    chain_cache: Mutex<HashMap<Pubkey, Arc<Mutex<Option<ChainEntry>>>>>,
+}
+
+impl AppState {
+    pub fn new(
+        indexer: SolanaIndexer,
+        store: Option<SqliteStore>,
+        address_book: AddressBook,
+        default_hours: u64,
+    ) -> Self {
+        Self {
+            indexer,
+            store: store.map(|store| SharedStore(Arc::new(std::sync::Mutex::new(store)))),
+            address_book,
+            default_hours,
+            feed: broadcast::channel(FEED_CAPACITY).0,
+            chain_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_since(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::hours(self.default_hours as i64)
+    }
+
+    // Labelled transfers of a wallet since the given time, from the store if
+    // there is one and from the chain otherwise, with where they came from
+    pub async fn load_transfers(
+        &self,
+        wallet: &Pubkey,
+        since: DateTime<Utc>,
+    ) -> Result<(&'static str, Vec<UsdcTransfer>)> {
+        let (source, mut transfers) = match &self.store {
+            Some(store) => {
+                let wallet = wallet.to_string();
+                ("store", store.query(move |store| store.load_transfers(&wallet, since)).await?)
+            }
+            None => ("chain", self.chain_transfers(wallet, since).await?),
+        };
+        self.address_book.label_transfers(&mut transfers);
+        Ok((source, transfers))
+    }
+
+    // Whether transfers since the given time can be served; from the chain
+    // only a limited range is indexed per request
+    pub fn check_since(&self, since: DateTime<Utc>) -> Result<(), String> {
+        let max_hours = self.default_hours.max(MAX_CHAIN_HOURS);
+        if self.store.is_none() && since < Utc::now() - Duration::hours(max_hours as i64) {
+            return Err(format!(
+                "Without --db, transfers are indexed from the chain for the last {} hours only",
+                max_hours
+            ));
+        }
+        Ok(())
+    }
+
+    // A wallet's transfers since the given time from the chain, reusing a
+    // recent fetch that reaches back far enough
+    async fn chain_transfers(&self, wallet: &Pubkey, since: DateTime<Utc>) -> Result<Vec<UsdcTransfer>> {
+        self.check_since(since).map_err(|e| anyhow!(e))?;
+        let now = Utc::now();
+
+        let entry = {
+            let mut cache = self.chain_cache.lock().await;
+            // Drop stale entries that no request is fetching into
+            cache.retain(|_, entry| entry.try_lock().map_or(true, |e| e.as_ref().is_some_and(|e| e.is_fresh(now))));
+            cache.entry(*wallet).or_default().clone()
+        };
+
+        let mut entry = entry.lock().await;
+        let reusable = entry.as_ref().is_some_and(|e| e.is_fresh(now) && e.since <= since);
+        if !reusable {
+            let hours = (now - since).num_hours().max(0) as u64 + 1;
+            let activity = self
+                .indexer
+                .get_wallet_activity(*wallet, hours, None, &mut FanOut::default())
+                .await?;
+            *entry = Some(ChainEntry {
+                fetched_at: now,
+                since,
+                transfers: activity.transfers,
+            });
+        }
+
+        Ok(entry
+            .iter()
+            .flat_map(|e| &e.transfers)
+            .filter(|t| t.timestamp >= since)
+            .cloned()
+            .collect())
+    }
+
+    // A transaction from the store, or fetched from the chain if the store
+    // does not have it
+    pub async fn transaction_details(&self, signature: &str) -> Result<Option<TransactionDetails>> {
+        if let Some(store) = &self.store {
+            let owned = signature.to_string();
+            let rows = store.query(move |store| store.load_signature(&owned)).await?;
+            if let Some(details) = stored_details(signature, rows, &self.address_book) {
+                return Ok(Some(details));
+            }
+        }
+        self.indexer.get_transaction_details(signature).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+// Time range, direction and minimum amount a transfer must match
+#[derive(Debug, Default)]
+pub struct TransferFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub direction: Option<Direction>,
+    // In USDC, e.g. 100.5
+    pub min_amount: Option<f64>,
+}
+
+impl TransferFilter {
+    pub fn check(&self) -> Result<(), String> {
+        match (self.since, self.until) {
+            (Some(since), Some(until)) if until <= since => Err("until must be after since".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, transfer: &UsdcTransfer) -> bool {
+        let min_raw = self.min_amount.map(|amount| (amount * 10f64.powi(USDC_DECIMALS as i32)).round() as u64);
+        self.since.is_none_or(|since| transfer.timestamp >= since)
+            && self.until.is_none_or(|until| transfer.timestamp < until)
+            && self.direction.is_none_or(|d| (d == Direction::In) == transfer.is_incoming)
+            && min_raw.is_none_or(|min| transfer.raw_amount >= min)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferQuery {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    direction: Option<Direction>,
+    min_amount: Option<f64>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferPage {
+    pub wallet: String,
+    pub source: &'static str,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub next_offset: Option<usize>,
+    pub transfers: Vec<UsdcTransfer>,
+}
+
+pub struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self(StatusCode::BAD_REQUEST, message.into())
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self(StatusCode::NOT_FOUND, message.into())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        warn!(error = %error, "Request failed");
+        Self(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/wallets/{address}/transfers", get(wallet_transfers))
+        .route("/transactions/{signature}", get(transaction))
+        .with_state(state.clone())
+        .merge(graphql::router(state))
+}
+
+pub async fn serve(addr: SocketAddr, state: AppState, poll_interval: std::time::Duration) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let state = Arc::new(state);
+    if state.store.is_some() {
+        tokio::spawn(follow_store(state.clone(), poll_interval));
+    }
+
+    info!(addr = %addr, store = state.store.is_some(), "Serving HTTP API");
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    info!("HTTP API stopped");
+    Ok(())
+}
+
+// Publish transfers that other runs (e.g. a cron job with --db) write to the
+// store. Rows updated by a rerun keep their id and are not published again.
+async fn follow_store(state: Arc<AppState>, poll_interval: std::time::Duration) {
+    let Some(store) = &state.store else { return };
+    let mut last_id = match store.query(|store| store.last_transfer_id()).await {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(error = %e, "Not following the store for new transfers");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(poll_interval);
+    loop {
+        interval.tick().await;
+        let rows = match store.query(move |store| store.load_transfers_after(last_id)).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                warn!(error = %e, "Failed to read new transfers from the store");
+                continue;
+            }
+        };
+
+        if !rows.is_empty() {
+            debug!(transfers = rows.len(), "New transfers in the store");
+        }
+        for (id, wallet, mut transfer) in rows {
+            last_id = id;
+            state.address_book.label_transfers(std::slice::from_mut(&mut transfer));
+            // Sending only fails when nobody is subscribed
+            let _ = state.feed.send(IndexedTransfer { wallet, transfer });
+        }
+    }
+}
+
+// Ctrl-C, or SIGTERM from the platform on deploys
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => futures::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// The process is up
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// The store and the RPC node can be reached
+async fn ready(State(state): State<Arc<AppState>>) -> Response {
+    let mut checks = serde_json::Map::new();
+    let mut ready = true;
+
+    if let Some(store) = &state.store {
+        let result = store.query(|store| store.ping()).await;
+        ready &= result.is_ok();
+        checks.insert("store".to_string(), check(result));
+    }
+    let result = state.indexer.get_health().await;
+    ready &= result.is_ok();
+    checks.insert("rpc".to_string(), check(result));
+
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = json!({ "status": if ready { "ready" } else { "unavailable" }, "checks": checks });
+    (status, Json(body)).into_response()
+}
+
+fn check(result: Result<()>) -> serde_json::Value {
+    match result {
+        Ok(()) => json!("ok"),
+        Err(e) => json!(e.to_string()),
+    }
+}
+
+async fn wallet_transfers(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+    Query(query): Query<TransferQuery>,
+) -> Result<Json<TransferPage>, ApiError> {
+    let wallet = Pubkey::from_str(&address).map_err(|e| ApiError::bad_request(format!("Invalid wallet address: {}", e)))?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_request(format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let offset = query.offset.unwrap_or(0);
+    let filter = TransferFilter {
+        since: Some(query.since.unwrap_or_else(|| state.default_since())),
+        until: query.until,
+        direction: query.direction,
+        min_amount: query.min_amount,
+    };
+    filter.check().map_err(ApiError::bad_request)?;
+    state.check_since(filter.since.unwrap_or_default()).map_err(ApiError::bad_request)?;
+
+    let (source, mut transfers) = state.load_transfers(&wallet, filter.since.unwrap_or_default()).await?;
+    transfers.retain(|t| filter.matches(t));
+
+    let total = transfers.len();
+    let page: Vec<UsdcTransfer> = transfers.into_iter().skip(offset).take(limit).collect();
+
+    Ok(Json(TransferPage {
+        wallet: address,
+        source,
+        total,
+        offset,
+        limit,
+        next_offset: (offset + limit < total).then_some(offset + limit),
+        transfers: page,
+    }))
+}
+
+async fn transaction(
+    State(state): State<Arc<AppState>>,
+    Path(signature): Path<String>,
+) -> Result<Json<TransactionDetails>, ApiError> {
+    Signature::from_str(&signature).map_err(|e| ApiError::bad_request(format!("Invalid signature: {}", e)))?;
+    state
+        .transaction_details(&signature)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("Transaction {} not found", signature)))
+}
+
+// Details of a transaction from the transfers indexed into the store
+fn stored_details(
+    signature: &str,
+    rows: Vec<(String, UsdcTransfer)>,
+    address_book: &AddressBook,
+) -> Option<TransactionDetails> {
+    let (_, first) = rows.first()?;
+    let details = TransactionDetails {
+        signature: signature.to_string(),
+        slot: first.slot,
+        timestamp: Some(first.timestamp),
+        fee_lamports: first.fee_lamports,
+        fee_payer: first.fee_payer.clone(),
+        error: first.error.clone(),
+        transfers: Vec::new(),
+        trace: None,
+    };
+
+    Some(TransactionDetails {
+        transfers: rows
+            .into_iter()
+            .map(|(wallet, mut transfer)| {
+                address_book.label_transfers(std::slice::from_mut(&mut transfer));
+                IndexedTransfer { wallet, transfer }
+            })
+            .collect(),
+        ..details
+    })
+}
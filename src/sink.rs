@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::parquet_export::ParquetSink;
+use crate::postgres::PostgresStore;
+use crate::sqlite::{to_text, SqliteStore};
+use crate::types::*;
+
+// Destination for transfers as the indexer parses them. Indexing a wallet
+// calls `begin`, then `write` once per processed batch, then `finish`;
+// `close` is called once after the last wallet.
+#[async_trait]
+pub trait TransferSink: Send {
+    async fn begin(&mut self, _wallet: &str, _hours_back: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()>;
+
+    async fn finish(&mut self, _wallet: &str, _transfers_found: usize) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Columns of the CSV format; append new ones at the end so existing
+// consumers keep working
+const CSV_HEADER: &[&str] = &[
+    "wallet",
+    "signature",
+    "timestamp",
+    "slot",
+    "tx_index",
+    "instruction_index",
+    "finality",
+    "direction",
+    "from_address",
+    "to_address",
+    "amount",
+    "is_internal",
+    "authority",
+    "authority_kind",
+    "error",
+    "program_path",
+    "from_label",
+    "to_label",
+];
+
+// Open a sink from a `--sink` spec: "json", "ndjson" or "csv" for stdout,
+// the same with ":<path>" for a file, "parquet:<dir>", "sqlite:<path>" or
+// "postgres:<url>"
+pub async fn open(spec: &str) -> Result<Box<dyn TransferSink>> {
+    let (kind, target) = match spec.split_once(':') {
+        Some((kind, target)) => (kind, Some(target)),
+        None => (spec, None),
+    };
+
+    let sink: Box<dyn TransferSink> = match (kind, target) {
+        ("json", path) => Box::new(JsonSink::new(open_writer(path)?)),
+        ("ndjson", path) => Box::new(NdjsonSink::new(open_writer(path)?)),
+        ("csv", path) => Box::new(CsvSink::new(open_writer(path)?)),
+        ("parquet", Some(dir)) => Box::new(ParquetSink::new(dir)),
+        ("sqlite", Some(path)) => Box::new(SqliteStore::open(path)?),
+        ("postgres", Some(url)) => Box::new(PostgresStore::connect(url).await?),
+        _ => {
+            return Err(anyhow!(
+                "Unknown sink {:?}, expected json, ndjson or csv (optionally with :<path>), parquet:<dir>, sqlite:<path> or postgres:<url>",
+                spec
+            ))
+        }
+    };
+    Ok(sink)
+}
+
+// Whether a `--sink` spec streams to stdout
+pub fn writes_stdout(spec: &str) -> bool {
+    matches!(spec, "json" | "ndjson" | "csv")
+}
+
+// Buffered writer for a file, or stdout without a path
+pub fn open_writer(path: Option<impl AsRef<Path>>) -> Result<Box<dyn Write + Send>> {
+    match path {
+        Some(path) => {
+            let file = File::create(path.as_ref())
+                .map_err(|e| anyhow!("Failed to create {}: {}", path.as_ref().display(), e))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+// A transfer together with the wallet it was indexed for
+#[derive(Serialize)]
+pub struct WalletTransfer<'a> {
+    pub wallet: &'a str,
+    #[serde(flatten)]
+    pub transfer: &'a UsdcTransfer,
+}
+
+// Streams transfers of all wallets as a single JSON array
+pub struct JsonSink {
+    writer: Box<dyn Write + Send>,
+    written: usize,
+}
+
+impl JsonSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self { writer, written: 0 }
+    }
+}
+
+#[async_trait]
+impl TransferSink for JsonSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        for transfer in transfers {
+            self.writer.write_all(if self.written == 0 { b"[\n" } else { b",\n" })?;
+            serde_json::to_writer(&mut self.writer, &WalletTransfer { wallet, transfer })?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.writer.write_all(if self.written == 0 { b"[]\n" } else { b"\n]\n" })?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Writes one JSON object per line as soon as each batch is parsed
+pub struct NdjsonSink {
+    writer: Box<dyn Write + Send>,
+}
+
+impl NdjsonSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self { writer }
+    }
+}
+
+#[async_trait]
+impl TransferSink for NdjsonSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        for transfer in transfers {
+            serde_json::to_writer(&mut self.writer, &WalletTransfer { wallet, transfer })?;
+            self.writer.write_all(b"\n")?;
+        }
+        // Flush per batch so consumers see transfers as they are parsed
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Writes CSV rows under the fixed CSV_HEADER, which is written even when
+// there are no transfers
+pub struct CsvSink {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+    header_written: bool,
+}
+
+impl CsvSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if !self.header_written {
+            self.writer.write_record(CSV_HEADER)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn record(wallet: &str, transfer: &UsdcTransfer) -> Vec<String> {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+
+        vec![
+            wallet.to_string(),
+            transfer.signature.clone(),
+            transfer.timestamp.to_rfc3339(),
+            transfer.slot.to_string(),
+            optional(transfer.tx_index.map(|i| i.to_string())),
+            optional(transfer.instruction_index.map(|i| i.to_string())),
+            optional(to_text(transfer.finality)),
+            if transfer.is_incoming { "in" } else { "out" }.to_string(),
+            transfer.from_address.clone(),
+            transfer.to_address.clone(),
+            format!("{:.6}", transfer.amount),
+            transfer.is_internal.to_string(),
+            optional(transfer.authority.clone()),
+            optional(to_text(transfer.authority_kind)),
+            optional(transfer.error.as_ref().map(|e| e.describe())),
+            transfer.program_path.join(" -> "),
+            optional(transfer.from_label.clone()),
+            optional(transfer.to_label.clone()),
+        ]
+    }
+}
+
+#[async_trait]
+impl TransferSink for CsvSink {
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.write_header()?;
+        for transfer in transfers {
+            self.writer.write_record(Self::record(wallet, transfer))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Forwards everything to several sinks in order
+#[derive(Default)]
+pub struct FanOut {
+    sinks: Vec<Box<dyn TransferSink>>,
+}
+
+impl FanOut {
+    pub fn push(&mut self, sink: Box<dyn TransferSink>) {
+        self.sinks.push(sink);
+    }
+}
+
+#[async_trait]
+impl TransferSink for FanOut {
+    async fn begin(&mut self, wallet: &str, hours_back: u64) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.begin(wallet, hours_back).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.write(wallet, transfers).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self, wallet: &str, transfers_found: usize) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.finish(wallet, transfers_found).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.close().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,733 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+use tracing::info;
+
+use crate::backfill::{BackfillChunk, BackfillJob, ChunkStatus};
+use crate::checkpoint::{Checkpoint, CheckpointStore};
+use crate::sink::TransferSink;
+use crate::types::*;
+
+// Schema migrations, applied in order. Never edit a released migration;
+// append a new one instead.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (
+        1,
+        "CREATE TABLE wallets (
+            address TEXT PRIMARY KEY,
+            first_indexed_at TEXT NOT NULL,
+            last_indexed_at TEXT NOT NULL
+        );
+
+        CREATE TABLE transactions (
+            signature TEXT PRIMARY KEY,
+            slot INTEGER NOT NULL,
+            tx_index INTEGER,
+            block_time TEXT NOT NULL,
+            finality TEXT,
+            error TEXT
+        );
+
+        CREATE TABLE transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            signature TEXT NOT NULL REFERENCES transactions(signature),
+            instruction_index INTEGER NOT NULL,
+            leg TEXT NOT NULL,
+            wallet TEXT NOT NULL,
+            from_address TEXT NOT NULL,
+            to_address TEXT NOT NULL,
+            amount REAL NOT NULL,
+            is_incoming INTEGER NOT NULL,
+            is_internal INTEGER NOT NULL,
+            timestamp TEXT NOT NULL,
+            slot INTEGER NOT NULL,
+            tx_index INTEGER,
+            finality TEXT,
+            authority TEXT,
+            authority_kind TEXT,
+            error TEXT,
+            program_path TEXT NOT NULL,
+            UNIQUE (signature, instruction_index, leg)
+        );
+
+        CREATE INDEX transfers_wallet_time ON transfers (wallet, timestamp);
+
+        CREATE TABLE runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            wallet TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            finished_at TEXT,
+            hours_back INTEGER NOT NULL,
+            transfers_found INTEGER
+        );",
+    ),
+    (
+        2,
+        "CREATE TABLE checkpoints (
+            wallet TEXT PRIMARY KEY,
+            signature TEXT NOT NULL,
+            slot INTEGER NOT NULL,
+            updated_at TEXT NOT NULL
+        );",
+    ),
+    (
+        3,
+        "CREATE TABLE backfill_jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            wallet TEXT NOT NULL,
+            hours_back INTEGER NOT NULL,
+            chunk_hours INTEGER NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            completed_at TEXT
+        );
+
+        CREATE TABLE backfill_chunks (
+            job_id INTEGER NOT NULL REFERENCES backfill_jobs(id),
+            chunk_index INTEGER NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            signatures TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            transfers_found INTEGER,
+            last_error TEXT,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (job_id, chunk_index)
+        );",
+    ),
+    (
+        4,
+        "ALTER TABLE transfers ADD COLUMN raw_amount INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE transfers ADD COLUMN decimals INTEGER NOT NULL DEFAULT 6;
+        UPDATE transfers SET raw_amount = CAST(ROUND(amount * 1000000) AS INTEGER);",
+    ),
+    (
+        5,
+        "ALTER TABLE transfers ADD COLUMN fee_lamports INTEGER;
+        ALTER TABLE transfers ADD COLUMN fee_payer TEXT;",
+    ),
+    (6, "ALTER TABLE transfers ADD COLUMN balance_after INTEGER;"),
+    (
+        7,
+        // SQLite cannot change a table constraint, so the table is rebuilt
+        // with the inner instruction position in its unique key
+        "CREATE TABLE transfers_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            signature TEXT NOT NULL REFERENCES transactions(signature),
+            instruction_index INTEGER NOT NULL,
+            inner_index INTEGER NOT NULL DEFAULT -1,
+            leg TEXT NOT NULL,
+            wallet TEXT NOT NULL,
+            from_address TEXT NOT NULL,
+            to_address TEXT NOT NULL,
+            amount REAL NOT NULL,
+            is_incoming INTEGER NOT NULL,
+            is_internal INTEGER NOT NULL,
+            timestamp TEXT NOT NULL,
+            slot INTEGER NOT NULL,
+            tx_index INTEGER,
+            finality TEXT,
+            authority TEXT,
+            authority_kind TEXT,
+            error TEXT,
+            program_path TEXT NOT NULL,
+            raw_amount INTEGER NOT NULL DEFAULT 0,
+            decimals INTEGER NOT NULL DEFAULT 6,
+            fee_lamports INTEGER,
+            fee_payer TEXT,
+            balance_after INTEGER,
+            UNIQUE (signature, instruction_index, inner_index, leg)
+        );
+
+        INSERT INTO transfers_new (
+            id, signature, instruction_index, leg, wallet, from_address, to_address,
+            amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+            authority, authority_kind, error, program_path, raw_amount, decimals,
+            fee_lamports, fee_payer, balance_after
+        )
+        SELECT
+            id, signature, instruction_index, leg, wallet, from_address, to_address,
+            amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+            authority, authority_kind, error, program_path, raw_amount, decimals,
+            fee_lamports, fee_payer, balance_after
+        FROM transfers;
+
+        DROP TABLE transfers;
+        ALTER TABLE transfers_new RENAME TO transfers;
+        CREATE INDEX transfers_wallet_time ON transfers (wallet, timestamp);",
+    ),
+    (
+        8,
+        // Jobs are saved before planning and record how far it got; older
+        // jobs were always fully planned
+        "ALTER TABLE backfill_jobs ADD COLUMN planned INTEGER NOT NULL DEFAULT 1;
+        ALTER TABLE backfill_jobs ADD COLUMN plan_cursor TEXT;",
+    ),
+    (
+        9,
+        // The wallet's token account joins the unique key, so two accounts
+        // of one wallet moved by the same instruction stay separate rows.
+        // Older rows keep an empty account until re-indexed, see
+        // `save_transfers`
+        "CREATE TABLE transfers_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            signature TEXT NOT NULL REFERENCES transactions(signature),
+            instruction_index INTEGER NOT NULL,
+            inner_index INTEGER NOT NULL DEFAULT -1,
+            leg TEXT NOT NULL,
+            token_account TEXT NOT NULL DEFAULT '',
+            wallet TEXT NOT NULL,
+            from_address TEXT NOT NULL,
+            to_address TEXT NOT NULL,
+            amount REAL NOT NULL,
+            is_incoming INTEGER NOT NULL,
+            is_internal INTEGER NOT NULL,
+            timestamp TEXT NOT NULL,
+            slot INTEGER NOT NULL,
+            tx_index INTEGER,
+            finality TEXT,
+            authority TEXT,
+            authority_kind TEXT,
+            error TEXT,
+            program_path TEXT NOT NULL,
+            raw_amount INTEGER NOT NULL DEFAULT 0,
+            decimals INTEGER NOT NULL DEFAULT 6,
+            fee_lamports INTEGER,
+            fee_payer TEXT,
+            balance_after INTEGER,
+            UNIQUE (signature, instruction_index, inner_index, leg, token_account)
+        );
+
+        INSERT INTO transfers_new (
+            id, signature, instruction_index, inner_index, leg, wallet, from_address, to_address,
+            amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+            authority, authority_kind, error, program_path, raw_amount, decimals,
+            fee_lamports, fee_payer, balance_after
+        )
+        SELECT
+            id, signature, instruction_index, inner_index, leg, wallet, from_address, to_address,
+            amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+            authority, authority_kind, error, program_path, raw_amount, decimals,
+            fee_lamports, fee_payer, balance_after
+        FROM transfers;
+
+        DROP TABLE transfers;
+        ALTER TABLE transfers_new RENAME TO transfers;
+        CREATE INDEX transfers_wallet_time ON transfers (wallet, timestamp);",
+    ),
+];
+
+// Transfer rows without an instruction index, and top-level transfers
+// without an inner index, are stored under -1
+const UNKNOWN_INSTRUCTION: i64 = -1;
+
+// SQLite database of indexed transfers
+pub struct SqliteStore {
+    conn: Connection,
+    // Run being recorded while used as a sink
+    run_id: Option<i64>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path.as_ref())
+            .map_err(|e| anyhow!("Failed to open database {}: {}", path.as_ref().display(), e))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        let mut store = Self { conn, run_id: None };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        let current: i64 = self.conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            [],
+            |row| row.get(0),
+        )?;
+
+        for (version, sql) in MIGRATIONS.iter().filter(|(v, _)| *v > current) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(sql)?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+                params![version, Utc::now()],
+            )?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    // Record the start of an indexing run, returning its id
+    pub fn begin_run(&self, wallet: &str, hours_back: u64) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO runs (wallet, started_at, hours_back) VALUES (?1, ?2, ?3)",
+            params![wallet, Utc::now(), hours_back as i64],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn finish_run(&self, run_id: i64, transfers_found: usize) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?1, transfers_found = ?2 WHERE id = ?3",
+            params![Utc::now(), transfers_found as i64, run_id],
+        )?;
+        Ok(())
+    }
+
+    // Upsert a wallet's transfers; re-running over an overlapping range
+    // updates the existing rows instead of duplicating them
+    pub fn save_transfers(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        let now = Utc::now();
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO wallets (address, first_indexed_at, last_indexed_at) VALUES (?1, ?2, ?2)
+             ON CONFLICT (address) DO UPDATE SET last_indexed_at = excluded.last_indexed_at",
+            params![wallet, now],
+        )?;
+
+        for transfer in transfers {
+            let finality = to_text(transfer.finality);
+            let error = transfer.error.as_ref().map(serde_json::to_string).transpose()?;
+
+            tx.execute(
+                "INSERT INTO transactions (signature, slot, tx_index, block_time, finality, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (signature) DO UPDATE SET
+                    tx_index = COALESCE(excluded.tx_index, transactions.tx_index),
+                    finality = COALESCE(excluded.finality, transactions.finality)",
+                params![
+                    transfer.signature,
+                    transfer.slot as i64,
+                    transfer.tx_index,
+                    transfer.timestamp,
+                    finality,
+                    error,
+                ],
+            )?;
+
+            let instruction_index = transfer.instruction_index.map(|i| i as i64).unwrap_or(UNKNOWN_INSTRUCTION);
+            let inner_index = transfer.inner_index.map(|i| i as i64).unwrap_or(UNKNOWN_INSTRUCTION);
+            let leg = Self::leg(wallet, transfer);
+            let token_account = transfer.token_account.as_deref().unwrap_or_default();
+
+            // Rows saved before the token account was recorded are claimed
+            // by the first leg with the same key, so re-indexing updates
+            // them instead of adding a copy
+            if !token_account.is_empty() {
+                tx.execute(
+                    "UPDATE transfers SET token_account = ?5
+                     WHERE id = (
+                        SELECT id FROM transfers
+                        WHERE signature = ?1 AND instruction_index = ?2 AND inner_index = ?3
+                            AND leg = ?4 AND token_account = ''
+                     )
+                     AND NOT EXISTS (
+                        SELECT 1 FROM transfers
+                        WHERE signature = ?1 AND instruction_index = ?2 AND inner_index = ?3
+                            AND leg = ?4 AND token_account = ?5
+                     )",
+                    params![transfer.signature, instruction_index, inner_index, leg, token_account],
+                )?;
+            }
+
+            tx.execute(
+                "INSERT INTO transfers (
+                    signature, instruction_index, leg, wallet, from_address, to_address,
+                    amount, is_incoming, is_internal, timestamp, slot, tx_index, finality,
+                    authority, authority_kind, error, program_path, raw_amount, decimals,
+                    fee_lamports, fee_payer, balance_after, inner_index, token_account
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24)
+                 ON CONFLICT (signature, instruction_index, inner_index, leg, token_account) DO UPDATE SET
+                    from_address = excluded.from_address,
+                    to_address = excluded.to_address,
+                    amount = excluded.amount,
+                    raw_amount = excluded.raw_amount,
+                    decimals = excluded.decimals,
+                    is_internal = excluded.is_internal,
+                    tx_index = COALESCE(excluded.tx_index, transfers.tx_index),
+                    finality = COALESCE(excluded.finality, transfers.finality),
+                    authority = excluded.authority,
+                    authority_kind = excluded.authority_kind,
+                    error = excluded.error,
+                    program_path = excluded.program_path,
+                    fee_lamports = COALESCE(excluded.fee_lamports, transfers.fee_lamports),
+                    fee_payer = COALESCE(excluded.fee_payer, transfers.fee_payer),
+                    balance_after = COALESCE(excluded.balance_after, transfers.balance_after)",
+                params![
+                    transfer.signature,
+                    instruction_index,
+                    leg,
+                    wallet,
+                    transfer.from_address,
+                    transfer.to_address,
+                    transfer.amount,
+                    transfer.is_incoming,
+                    transfer.is_internal,
+                    transfer.timestamp,
+                    transfer.slot as i64,
+                    transfer.tx_index,
+                    finality,
+                    transfer.authority,
+                    to_text(transfer.authority_kind),
+                    error,
+                    serde_json::to_string(&transfer.program_path)?,
+                    transfer.raw_amount as i64,
+                    transfer.decimals,
+                    transfer.fee_lamports.map(|f| f as i64),
+                    transfer.fee_payer,
+                    transfer.balance_after.map(|b| b as i64),
+                    inner_index,
+                    token_account,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Check the database can still be queried
+    pub fn ping(&self) -> Result<()> {
+        self.conn.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    // Transfers of a wallet since the given time, newest first
+    pub fn load_transfers(&self, wallet: &str, since: DateTime<Utc>) -> Result<Vec<UsdcTransfer>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {TRANSFER_COLUMNS}
+             FROM transfers
+             WHERE wallet = ?1 AND timestamp >= ?2
+             ORDER BY slot DESC, tx_index DESC, signature, instruction_index, inner_index, token_account"
+        ))?;
+
+        let transfers = stmt
+            .query_map(params![wallet, since], transfer_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(transfers)
+    }
+
+    // Transfers of one transaction with the wallet each was indexed for
+    pub fn load_signature(&self, signature: &str) -> Result<Vec<(String, UsdcTransfer)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {TRANSFER_COLUMNS}, wallet
+             FROM transfers
+             WHERE signature = ?1
+             ORDER BY wallet, instruction_index, inner_index, leg, token_account"
+        ))?;
+
+        let transfers = stmt
+            .query_map(params![signature], |row| Ok((row.get(22)?, transfer_from_row(row)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(transfers)
+    }
+
+    // Id of the newest transfer row, 0 for an empty table
+    pub fn last_transfer_id(&self) -> Result<i64> {
+        let id = self.conn.query_row("SELECT COALESCE(MAX(id), 0) FROM transfers", [], |row| row.get(0))?;
+        Ok(id)
+    }
+
+    // Transfer rows inserted after the given id, oldest first, with their id
+    // and the wallet each was indexed for
+    pub fn load_transfers_after(&self, id: i64) -> Result<Vec<(i64, String, UsdcTransfer)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {TRANSFER_COLUMNS}, wallet, id
+             FROM transfers
+             WHERE id > ?1
+             ORDER BY id
+             LIMIT 1000"
+        ))?;
+
+        let transfers = stmt
+            .query_map(params![id], |row| Ok((row.get(23)?, row.get(22)?, transfer_from_row(row)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(transfers)
+    }
+
+    // Addresses of all wallets with indexed transfers
+    pub fn load_wallets(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT address FROM wallets ORDER BY address")?;
+        let wallets = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(wallets)
+    }
+
+    // Latest unfinished backfill job of a wallet with the same range and chunk size
+    pub fn find_open_job(&self, wallet: &str, hours_back: u64, chunk_hours: u64) -> Result<Option<BackfillJob>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, wallet, start_time, end_time, planned, plan_cursor FROM backfill_jobs
+             WHERE wallet = ?1 AND hours_back = ?2 AND chunk_hours = ?3 AND completed_at IS NULL
+             ORDER BY id DESC LIMIT 1",
+        )?;
+        let mut jobs = stmt.query_map(params![wallet, hours_back as i64, chunk_hours as i64], |row| {
+            Ok(BackfillJob {
+                id: row.get(0)?,
+                wallet: row.get(1)?,
+                start: row.get(2)?,
+                end: row.get(3)?,
+                planned: row.get(4)?,
+                plan_cursor: row.get(5)?,
+            })
+        })?;
+        Ok(jobs.next().transpose()?)
+    }
+
+    // Record a new job and its still empty chunks; planning fills them in
+    // with `add_planned_signatures`
+    pub fn create_job(
+        &mut self,
+        wallet: &str,
+        hours_back: u64,
+        chunk_hours: u64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        chunks: &[BackfillChunk],
+    ) -> Result<BackfillJob> {
+        let now = Utc::now();
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO backfill_jobs (wallet, hours_back, chunk_hours, start_time, end_time, created_at, planned)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0)",
+            params![wallet, hours_back as i64, chunk_hours as i64, start, end, now],
+        )?;
+        let job_id = tx.last_insert_rowid();
+
+        for chunk in chunks {
+            tx.execute(
+                "INSERT INTO backfill_chunks (
+                    job_id, chunk_index, start_time, end_time, signatures, status, attempts, updated_at
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    job_id,
+                    chunk.index as i64,
+                    chunk.start,
+                    chunk.end,
+                    serde_json::to_string(&chunk.signatures)?,
+                    to_text(Some(chunk.status)),
+                    chunk.attempts,
+                    now,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(BackfillJob {
+            id: job_id,
+            wallet: wallet.to_string(),
+            start,
+            end,
+            planned: false,
+            plan_cursor: None,
+        })
+    }
+
+    // Append one page of planned signatures to the job's chunks, indexed by
+    // chunk, together with the cursor of the next page; no cursor means
+    // planning is done
+    pub fn add_planned_signatures(
+        &mut self,
+        job_id: i64,
+        signatures: &[Vec<GetSignaturesForAddressResponse>],
+        next_cursor: Option<&str>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+
+        for (index, page) in signatures.iter().enumerate().filter(|(_, page)| !page.is_empty()) {
+            let stored: String = tx.query_row(
+                "SELECT signatures FROM backfill_chunks WHERE job_id = ?1 AND chunk_index = ?2",
+                params![job_id, index as i64],
+                |row| row.get(0),
+            )?;
+            let mut chunk_signatures: Vec<GetSignaturesForAddressResponse> = serde_json::from_str(&stored)?;
+            chunk_signatures.extend(page.iter().cloned());
+            tx.execute(
+                "UPDATE backfill_chunks SET signatures = ?1, updated_at = ?2 WHERE job_id = ?3 AND chunk_index = ?4",
+                params![serde_json::to_string(&chunk_signatures)?, Utc::now(), job_id, index as i64],
+            )?;
+        }
+
+        tx.execute(
+            "UPDATE backfill_jobs SET plan_cursor = ?1, planned = ?2 WHERE id = ?3",
+            params![next_cursor, next_cursor.is_none(), job_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_chunks(&self, job_id: i64) -> Result<Vec<BackfillChunk>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT chunk_index, start_time, end_time, signatures, status, attempts, transfers_found, last_error
+             FROM backfill_chunks WHERE job_id = ?1 ORDER BY chunk_index",
+        )?;
+
+        let chunks = stmt
+            .query_map(params![job_id], |row| {
+                Ok(BackfillChunk {
+                    index: row.get::<_, i64>(0)? as usize,
+                    start: row.get(1)?,
+                    end: row.get(2)?,
+                    signatures: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or_default(),
+                    status: from_text(row.get(4)?).unwrap_or(ChunkStatus::Pending),
+                    attempts: row.get(5)?,
+                    transfers_found: row.get::<_, Option<i64>>(6)?.map(|n| n as usize),
+                    last_error: row.get(7)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(chunks)
+    }
+
+    pub fn update_chunk(&self, job_id: i64, chunk: &BackfillChunk) -> Result<()> {
+        self.conn.execute(
+            "UPDATE backfill_chunks
+             SET status = ?1, attempts = ?2, transfers_found = ?3, last_error = ?4, updated_at = ?5
+             WHERE job_id = ?6 AND chunk_index = ?7",
+            params![
+                to_text(Some(chunk.status)),
+                chunk.attempts,
+                chunk.transfers_found.map(|n| n as i64),
+                chunk.last_error,
+                Utc::now(),
+                job_id,
+                chunk.index as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn complete_job(&self, job_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE backfill_jobs SET completed_at = ?1 WHERE id = ?2",
+            params![Utc::now(), job_id],
+        )?;
+        Ok(())
+    }
+
+    // Which side of a transfer the wallet is on, e.g. "out:<wallet>"
+    fn leg(wallet: &str, transfer: &UsdcTransfer) -> String {
+        let direction = if transfer.is_incoming { "in" } else { "out" };
+        format!("{}:{}", direction, wallet)
+    }
+}
+
+#[async_trait]
+impl TransferSink for SqliteStore {
+    async fn begin(&mut self, wallet: &str, hours_back: u64) -> Result<()> {
+        self.run_id = Some(self.begin_run(wallet, hours_back)?);
+        Ok(())
+    }
+
+    async fn write(&mut self, wallet: &str, transfers: &[UsdcTransfer]) -> Result<()> {
+        self.save_transfers(wallet, transfers)
+    }
+
+    async fn finish(&mut self, wallet: &str, transfers_found: usize) -> Result<()> {
+        if let Some(run_id) = self.run_id.take() {
+            self.finish_run(run_id, transfers_found)?;
+        }
+        info!(wallet, transfers = transfers_found, "Saved transfers to SQLite");
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for SqliteStore {
+    async fn load_checkpoint(&mut self, wallet: &str) -> Result<Option<Checkpoint>> {
+        let mut stmt = self.conn.prepare("SELECT signature, slot FROM checkpoints WHERE wallet = ?1")?;
+        let mut rows = stmt.query_map(params![wallet], |row| {
+            Ok(Checkpoint {
+                signature: row.get(0)?,
+                slot: row.get::<_, i64>(1)? as u64,
+            })
+        })?;
+        Ok(rows.next().transpose()?)
+    }
+
+    async fn save_checkpoint(&mut self, wallet: &str, checkpoint: &Checkpoint) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO checkpoints (wallet, signature, slot, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (wallet) DO UPDATE SET
+                signature = excluded.signature,
+                slot = excluded.slot,
+                updated_at = excluded.updated_at",
+            params![wallet, checkpoint.signature, checkpoint.slot as i64, Utc::now()],
+        )?;
+        Ok(())
+    }
+}
+
+// Columns read by `transfer_from_row`, in order
+const TRANSFER_COLUMNS: &str = "signature, timestamp, from_address, to_address, amount, is_incoming,
+    slot, tx_index, instruction_index, finality, is_internal,
+    authority, authority_kind, error, program_path, raw_amount, decimals,
+    fee_lamports, fee_payer, balance_after, inner_index, token_account";
+
+fn transfer_from_row(row: &rusqlite::Row) -> rusqlite::Result<UsdcTransfer> {
+    let instruction_index: i64 = row.get(8)?;
+    let inner_index: i64 = row.get(20)?;
+
+    Ok(UsdcTransfer {
+        signature: row.get(0)?,
+        timestamp: row.get(1)?,
+        from_address: row.get(2)?,
+        to_address: row.get(3)?,
+        amount: row.get(4)?,
+        is_incoming: row.get(5)?,
+        raw_amount: row.get::<_, i64>(15)? as u64,
+        decimals: row.get(16)?,
+        slot: row.get::<_, i64>(6)? as u64,
+        tx_index: row.get(7)?,
+        instruction_index: (instruction_index != UNKNOWN_INSTRUCTION)
+            .then_some(instruction_index as usize),
+        inner_index: (inner_index != UNKNOWN_INSTRUCTION).then_some(inner_index as usize),
+        token_account: Some(row.get::<_, String>(21)?).filter(|a| !a.is_empty()),
+        finality: from_text(row.get(9)?),
+        is_internal: row.get(10)?,
+        authority: row.get(11)?,
+        authority_kind: from_text(row.get(12)?),
+        error: row.get::<_, Option<String>>(13)?
+            .and_then(|e| serde_json::from_str(&e).ok()),
+        program_path: serde_json::from_str(&row.get::<_, String>(14)?)
+            .unwrap_or_default(),
+        fee_lamports: row.get::<_, Option<i64>>(17)?.map(|f| f as u64),
+        fee_payer: row.get(18)?,
+        balance_after: row.get::<_, Option<i64>>(19)?.map(|b| b as u64),
+        from_label: None,
+        to_label: None,
+    })
+}
+
+// Unit enums such as `Finality` are stored as their serde name
+pub fn to_text<T: Serialize>(value: Option<T>) -> Option<String> {
+    value
+        .and_then(|v| serde_json::to_value(v).ok())
+        .and_then(|v| v.as_str().map(str::to_string))
+}
+
+fn from_text<T: DeserializeOwned>(text: Option<String>) -> Option<T> {
+    text.and_then(|t| serde_json::from_value(serde_json::Value::String(t)).ok())
+}
@@ -0,0 +1,111 @@
+use serde::Serialize;
+
+use crate::addressbook::program_name;
+use crate::instructions::{InstructionDecoder, RawInstruction};
+use crate::types::*;
+
+// Program path with known programs replaced by their names
+pub fn label_path(path: &[String]) -> Vec<String> {
+    path.iter()
+        .map(|id| program_name(id).map(str::to_string).unwrap_or_else(|| id.clone()))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvocationStatus {
+    Success,
+    Failed { reason: String },
+    // No matching log line, e.g. logs were truncated or not returned
+    Unknown,
+}
+
+// One program invocation and the CPIs it made
+#[derive(Debug, Clone, Serialize)]
+pub struct Invocation {
+    pub program_id: String,
+    pub program_name: Option<String>,
+    pub instruction_index: usize,
+    pub depth: u32,
+    pub status: InvocationStatus,
+    pub children: Vec<Invocation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionTrace {
+    pub signature: String,
+    pub invocations: Vec<Invocation>,
+}
+
+impl TransactionTrace {
+    pub fn build(tx: &TransactionResponse) -> Self {
+        let instructions = InstructionDecoder::instructions(tx);
+        let logs = tx
+            .meta
+            .as_ref()
+            .and_then(|m| m.log_messages.as_deref())
+            .unwrap_or_default();
+        let statuses = Self::statuses_from_logs(logs, &instructions);
+
+        let mut roots: Vec<Invocation> = Vec::new();
+        for (ix, status) in instructions.iter().zip(statuses) {
+            let invocation = Invocation {
+                program_id: ix.program_id.clone(),
+                program_name: program_name(&ix.program_id).map(str::to_string),
+                instruction_index: ix.instruction_index,
+                depth: ix.stack_height,
+                status,
+                children: Vec::new(),
+            };
+
+            Self::attach(&mut roots, ix.stack_height - 1, invocation);
+        }
+
+        Self {
+            signature: tx.transaction.signatures.first().cloned().unwrap_or_default(),
+            invocations: roots,
+        }
+    }
+
+    // Attach under the most recent invocation `levels` deep
+    fn attach(siblings: &mut Vec<Invocation>, levels: u32, invocation: Invocation) {
+        if levels > 0 {
+            if let Some(parent) = siblings.last_mut() {
+                return Self::attach(&mut parent.children, levels - 1, invocation);
+            }
+        }
+        siblings.push(invocation);
+    }
+
+    // Match "Program X invoke [n]" / "Program X success" / "Program X failed: ..."
+    // lines to instructions. Both are in execution order, so the k-th invoke
+    // line belongs to the k-th instruction.
+    fn statuses_from_logs(logs: &[String], instructions: &[RawInstruction]) -> Vec<InvocationStatus> {
+        let mut statuses = vec![InvocationStatus::Unknown; instructions.len()];
+        let mut open: Vec<usize> = Vec::new();
+        let mut next = 0;
+
+        for line in logs {
+            let Some(rest) = line.strip_prefix("Program ") else { continue };
+            let Some((program, event)) = rest.split_once(' ') else { continue };
+
+            if event.starts_with("invoke [") {
+                // Stop matching if the logs and instructions diverge
+                if instructions.get(next).map(|ix| ix.program_id.as_str()) != Some(program) {
+                    break;
+                }
+                open.push(next);
+                next += 1;
+            } else if event == "success" || event.starts_with("failed") {
+                let Some(index) = open.pop() else { continue };
+                statuses[index] = match event.strip_prefix("failed: ") {
+                    Some(reason) => InvocationStatus::Failed { reason: reason.to_string() },
+                    None if event == "success" => InvocationStatus::Success,
+                    None => InvocationStatus::Failed { reason: event.to_string() },
+                };
+            }
+        }
+
+        statuses
+    }
+}
@@ -1,13 +1,26 @@
+// RPC response types mirror the JSON-RPC schema, so not every field is read
+#![allow(dead_code)]
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use solana_sdk::pubkey::Pubkey;
+
+use crate::checkpoint::Checkpoint;
+use crate::errors::TransactionError;
+use crate::trace::TransactionTrace;
 
 // USDC mint address on Solana mainnet
 pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+pub const USDC_DECIMALS: u8 = 6;
 
 // SPL Token program ID
 pub const SPL_TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
 
+// Associated Token Account program ID
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+// System program ID
+pub const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsdcTransfer {
     pub signature: String,
@@ -16,6 +29,193 @@ pub struct UsdcTransfer {
     pub to_address: String,
     pub amount: f64,
     pub is_incoming: bool,
+    // Exact amount in base units and the mint's decimals (USDC has 6)
+    #[serde(default)]
+    pub raw_amount: u64,
+    #[serde(default = "usdc_decimals")]
+    pub decimals: u8,
+    // Position in the chain: slot, index within the block and instruction index
+    #[serde(default)]
+    pub slot: u64,
+    #[serde(default)]
+    pub tx_index: Option<u32>,
+    #[serde(default)]
+    pub instruction_index: Option<usize>,
+    // Position among the inner instructions of `instruction_index` for
+    // transfers made by a CPI, so legs under one instruction stay distinct
+    #[serde(default)]
+    pub inner_index: Option<usize>,
+    // The wallet's own USDC token account on this side of the transfer; one
+    // instruction can move several accounts of the same wallet
+    #[serde(default)]
+    pub token_account: Option<String>,
+    #[serde(default)]
+    pub finality: Option<Finality>,
+    // Both sides belong to the same wallet group
+    #[serde(default)]
+    pub is_internal: bool,
+    // Account that signed an outgoing transfer
+    #[serde(default)]
+    pub authority: Option<String>,
+    #[serde(default)]
+    pub authority_kind: Option<AuthorityKind>,
+    // Set for attempts from failed transactions, see `moved_funds`
+    #[serde(default)]
+    pub error: Option<TransactionError>,
+    // Programs that caused the movement, e.g. ["Jupiter", "Whirlpool", "Token"]
+    #[serde(default)]
+    pub program_path: Vec<String>,
+    // Fee of the whole transaction in lamports and the account that paid it
+    #[serde(default)]
+    pub fee_lamports: Option<u64>,
+    #[serde(default)]
+    pub fee_payer: Option<String>,
+    // USDC balance of the wallet after the transaction, in base units, summed
+    // over all its USDC accounts (see `balance::AccountBalances`); None where
+    // that set of accounts is unknown
+    #[serde(default)]
+    pub balance_after: Option<u64>,
+    // Address book labels of both sides, if known
+    #[serde(default)]
+    pub from_label: Option<String>,
+    #[serde(default)]
+    pub to_label: Option<String>,
+}
+
+impl UsdcTransfer {
+    // Attempts from failed transactions are kept to show what was tried, but
+    // they moved no funds, so totals, balances and statements leave them out
+    pub fn moved_funds(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+fn usdc_decimals() -> u8 {
+    USDC_DECIMALS
+}
+
+// Commitment level the transaction had reached when it was indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Finality {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorityKind {
+    Owner,
+    Delegate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelegationKind {
+    Approve,
+    Revoke,
+    DelegatedSpend,
+}
+
+// Approve/Revoke on one of the wallet's USDC accounts, or a transfer signed by its delegate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelegationEvent {
+    pub signature: String,
+    pub timestamp: DateTime<Utc>,
+    pub slot: u64,
+    pub tx_index: Option<u32>,
+    pub instruction_index: usize,
+    pub token_account: String,
+    pub kind: DelegationKind,
+    pub delegate: Option<String>,
+    pub amount: f64,
+    // Remaining allowance after this event, if known
+    pub allowance: Option<f64>,
+}
+
+// Delegation still outstanding at the end of the indexed range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delegation {
+    pub token_account: String,
+    pub delegate: String,
+    pub allowance: f64,
+    pub approved_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleKind {
+    Initialize,
+    CreateAssociated,
+    Close,
+    Freeze,
+    Thaw,
+    OwnerChange,
+}
+
+// Creation, closing, freezing or ownership change of one of the wallet's USDC accounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleEvent {
+    pub signature: String,
+    pub timestamp: DateTime<Utc>,
+    pub slot: u64,
+    pub tx_index: Option<u32>,
+    pub instruction_index: usize,
+    pub token_account: String,
+    pub kind: LifecycleKind,
+    // Rent paid on creation or reclaimed on close
+    pub rent_lamports: Option<u64>,
+    pub rent_payer: Option<String>,
+    pub rent_recipient: Option<String>,
+    // Signer of the instruction (owner, freeze authority or previous owner)
+    pub authority: Option<String>,
+    pub new_owner: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct WalletActivity {
+    pub transfers: Vec<UsdcTransfer>,
+    pub delegations: Vec<DelegationEvent>,
+    pub outstanding_delegations: Vec<Delegation>,
+    pub lifecycle: Vec<LifecycleEvent>,
+    // Full invocation trees, only collected when tracing is enabled
+    pub traces: Vec<TransactionTrace>,
+    // Newest signature seen by this run, to resume from next time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<Checkpoint>,
+    // USDC balance before the first and after the last transfer, when the
+    // post token balances allow reconstructing them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opening_balance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closing_balance: Option<f64>,
+    // Transactions that could not be fetched or parsed and were skipped; a
+    // checkpoint must not move past them
+    #[serde(skip)]
+    pub skipped: usize,
+}
+
+// A transfer together with the wallet it was indexed for
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedTransfer {
+    pub wallet: String,
+    #[serde(flatten)]
+    pub transfer: UsdcTransfer,
+}
+
+// A transaction with the USDC transfers of every wallet it touched
+#[derive(Debug, Serialize)]
+pub struct TransactionDetails {
+    pub signature: String,
+    pub slot: u64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub fee_lamports: Option<u64>,
+    pub fee_payer: Option<String>,
+    pub error: Option<TransactionError>,
+    pub transfers: Vec<IndexedTransfer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TransactionTrace>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -25,14 +225,15 @@ pub struct RpcResponse<T> {
     pub id: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetSignaturesForAddressResponse {
     pub signature: String,
     pub slot: u64,
     pub err: Option<serde_json::Value>,
     #[serde(rename = "blockTime")]
     pub block_time: Option<i64>,
-    pub confirmation_status: Option<String>,
+    #[serde(rename = "confirmationStatus")]
+    pub confirmation_status: Option<Finality>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,6 +245,30 @@ pub struct TransactionResponse {
     pub meta: Option<TransactionMeta>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BlockSignaturesResponse {
+    pub signatures: Vec<String>,
+    #[serde(rename = "blockTime")]
+    pub block_time: Option<i64>,
+}
+
+// Responses with a context slot, e.g. getTokenAccountsByOwner
+#[derive(Debug, Deserialize)]
+pub struct RpcContextResponse<T> {
+    pub context: RpcContext,
+    pub value: T,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcContext {
+    pub slot: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyedAccount {
+    pub pubkey: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TransactionData {
     pub message: TransactionMessage,
@@ -63,8 +288,8 @@ pub struct TransactionMessage {
 pub struct TransactionInstruction {
     pub accounts: Vec<u8>,
     pub data: String,
-    #[serde(rename = "programId")]
-    pub program_id: String,
+    #[serde(rename = "programIdIndex")]
+    pub program_id_index: u8,
     #[serde(rename = "stackHeight")]
     pub stack_height: Option<u32>,
 }
@@ -86,6 +311,14 @@ pub struct TransactionMeta {
     #[serde(rename = "preTokenBalances")]
     pub pre_token_balances: Option<Vec<TokenBalance>>,
     pub status: serde_json::Value,
+    #[serde(rename = "loadedAddresses")]
+    pub loaded_addresses: Option<LoadedAddresses>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoadedAddresses {
+    pub writable: Vec<String>,
+    pub readonly: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -98,8 +331,8 @@ pub struct InnerInstructions {
 pub struct InnerInstruction {
     pub accounts: Vec<u8>,
     pub data: String,
-    #[serde(rename = "programId")]
-    pub program_id: String,
+    #[serde(rename = "programIdIndex")]
+    pub program_id_index: u8,
     #[serde(rename = "stackHeight")]
     pub stack_height: Option<u32>,
 }
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use tracing::{info, warn};
+
+use crate::balance::{chain_order, opening_balance, signed_amount, ui_amount};
+use crate::indexer::SolanaIndexer;
+use crate::types::*;
+
+#[derive(Debug, Serialize)]
+pub struct TokenAccountBalance {
+    pub address: String,
+    pub balance: f64,
+}
+
+// A transaction whose balance change is not explained by the transfers
+// parsed from it
+#[derive(Debug, Serialize)]
+pub struct Discrepancy {
+    pub signature: String,
+    pub slot: u64,
+    pub timestamp: DateTime<Utc>,
+    pub parsed_delta: f64,
+    pub observed_delta: f64,
+    pub unexplained: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub wallet: String,
+    pub opening_balance: f64,
+    pub indexed_balance: f64,
+    pub onchain_balance: f64,
+    pub difference: f64,
+    pub matches: bool,
+    pub accounts: Vec<TokenAccountBalance>,
+    pub discrepancies: Vec<Discrepancy>,
+    // Difference left after the last indexed transaction: activity after the
+    // indexed range, or transactions that were never indexed
+    pub unexplained_after_last: f64,
+}
+
+// One transaction in chain order with its parsed change and the balance
+// recorded after it
+struct Step<'a> {
+    first: &'a UsdcTransfer,
+    delta: i128,
+    balance_after: Option<i128>,
+}
+
+// Compare a wallet's indexed closing balance (opening balance plus every
+// parsed change) with the current balances of its USDC token accounts
+pub async fn verify_wallet(indexer: &SolanaIndexer, wallet: &Pubkey, activity: &WalletActivity) -> Result<Verification> {
+    let mut accounts = Vec::new();
+    let mut onchain = 0i128;
+    for address in indexer.get_usdc_accounts(wallet).await? {
+        let raw = indexer.get_token_account_balance(&address).await?;
+        onchain += raw as i128;
+        accounts.push(TokenAccountBalance {
+            address,
+            balance: ui_amount(raw as i128),
+        });
+    }
+    info!(wallet = %wallet, accounts = accounts.len(), "Fetched on-chain USDC balances");
+
+    let mut transfers: Vec<&UsdcTransfer> = activity.transfers.iter().collect();
+    transfers.sort_by(|a, b| chain_order(a, b));
+
+    let opening = opening_balance(transfers.iter().copied()).unwrap_or_else(|| {
+        warn!(wallet = %wallet, "No balances recorded for the wallet; verifying from a zero opening balance");
+        0
+    });
+
+    let steps: Vec<Step> = transfers
+        .chunk_by(|a, b| a.signature == b.signature)
+        .map(|tx| Step {
+            first: tx[0],
+            delta: tx.iter().map(|t| signed_amount(t)).sum(),
+            balance_after: tx.iter().find_map(|t| t.balance_after).map(|b| b as i128),
+        })
+        .collect();
+
+    let indexed = opening + steps.iter().map(|s| s.delta).sum::<i128>();
+    let (discrepancies, drift) = find_discrepancies(opening, &steps);
+
+    Ok(Verification {
+        wallet: wallet.to_string(),
+        opening_balance: ui_amount(opening),
+        indexed_balance: ui_amount(indexed),
+        onchain_balance: ui_amount(onchain),
+        difference: ui_amount(onchain - indexed),
+        matches: indexed == onchain,
+        accounts,
+        discrepancies,
+        unexplained_after_last: ui_amount(onchain - indexed - drift),
+    })
+}
+
+// Find the transactions whose recorded balance drifts from the one predicted
+// by the parsed changes. The drift after each transaction is the recorded
+// balance minus opening plus parsed changes so far; every transaction where
+// it changes is where unexplained funds moved. Drift can return to an earlier
+// value (funds missed in, then out), so every recorded balance is compared.
+// Returns the discrepancies and the drift after the last transaction.
+fn find_discrepancies(opening: i128, steps: &[Step]) -> (Vec<Discrepancy>, i128) {
+    let mut discrepancies = Vec::new();
+    let mut predicted = opening;
+    let mut current = 0;
+    // Recorded (or predicted) balance after the last checked transaction, and
+    // the parsed change since then
+    let mut before = opening;
+    let mut parsed = 0;
+
+    for step in steps {
+        predicted += step.delta;
+        parsed += step.delta;
+
+        // Transactions without a recorded balance cannot be checked
+        let Some(recorded) = step.balance_after else { continue };
+        let drift = recorded - predicted;
+        if drift != current {
+            let observed = recorded - before;
+            discrepancies.push(Discrepancy {
+                signature: step.first.signature.clone(),
+                slot: step.first.slot,
+                timestamp: step.first.timestamp,
+                parsed_delta: ui_amount(parsed),
+                observed_delta: ui_amount(observed),
+                unexplained: ui_amount(observed - parsed),
+            });
+            current = drift;
+        }
+        before = recorded;
+        parsed = 0;
+    }
+
+    (discrepancies, current)
+}
@@ -0,0 +1,9 @@
+// Text escaped for XML content and attribute values, as used by the camt.053
+// and OFX statements
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}